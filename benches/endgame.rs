@@ -0,0 +1,81 @@
+//! 終盤探索の手の並べ方 (と並べるのに使う評価関数) による探索ノード数の比較です。
+//!
+//! ```
+//! cargo bench --bench endgame
+//! ```
+
+use fl_reversi_rs::depth_first_search::{Solver, Window};
+use fl_reversi_rs::eval::{self, DiscCount, Evaluator, Mobility};
+use fl_reversi_rs::move_order::MoveOrdering;
+use fl_reversi_rs::transposition::TranspositionTable;
+use fl_reversi_rs::{Board, Color, Move};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::Instant;
+
+const EMPTIES: u64 = 14;
+const POSITIONS: u64 = 8;
+
+/// 乱数の種から決まる、`EMPTIES` 個の空きマスがある局面
+fn fixed_position(seed: u64) -> (Board, Color) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    loop {
+        let mut board = Board::new();
+        let mut color = Color::Black;
+        let mut passed = false;
+        while 64 - board.sum_stones() > EMPTIES {
+            let moves = board.valid_moves(color);
+            if moves.is_empty() {
+                if passed {
+                    break;
+                }
+                passed = true;
+            } else {
+                passed = false;
+                board.do_move(Move::Mv(moves[rng.gen_range(0..moves.len())]), color);
+            }
+            color = color.opposite();
+        }
+        if 64 - board.sum_stones() == EMPTIES {
+            return (board, color);
+        }
+    }
+}
+
+fn main() {
+    let positions: Vec<_> = (0..POSITIONS).map(fixed_position).collect();
+    let (alpha, beta) = Window::Exact.bounds();
+    let weights = eval::weights();
+    let cases: [(&str, MoveOrdering, &dyn Evaluator); 5] = [
+        ("None", MoveOrdering::None, &Mobility),
+        ("Evaluation(mobility)", MoveOrdering::Evaluation, &Mobility),
+        (
+            "Evaluation(disc count)",
+            MoveOrdering::Evaluation,
+            &DiscCount,
+        ),
+        ("Evaluation(pattern)", MoveOrdering::Evaluation, weights),
+        ("FastestFirst", MoveOrdering::FastestFirst, &Mobility),
+    ];
+    for (name, ordering, evaluator) in cases {
+        let mut total_nodes = 0;
+        let start = Instant::now();
+        for (board, color) in &positions {
+            let tt = TranspositionTable::new(1 << 18);
+            let mut solver = Solver::new(&tt, Instant::now(), i32::MAX)
+                .with_ordering(ordering)
+                .with_evaluator(evaluator);
+            let mut board = board.clone();
+            solver
+                .solve(&mut board, *color, alpha, beta, false)
+                .expect("no time limit");
+            total_nodes += solver.nodes();
+        }
+        let elapsed = start.elapsed();
+        println!(
+            "{name}: {total_nodes} nodes, {:.3}s ({:.0} nodes/s)",
+            elapsed.as_secs_f64(),
+            total_nodes as f64 / elapsed.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,121 @@
+//! 合法手の列挙方法による速度の比較です。
+//!
+//! ```
+//! cargo bench --bench movegen
+//! ```
+
+use fl_reversi_rs::bit_othello::bit_count;
+use fl_reversi_rs::{Board, Color};
+use std::time::Instant;
+
+/// `valid_moves` (Vec を確保) で合法手を列挙する perft
+fn perft_vec(board: &mut Board, color: Color, depth: u32, prev_is_pass: bool) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let moves = board.valid_moves(color);
+    if moves.is_empty() {
+        if prev_is_pass {
+            return 1;
+        }
+        return perft_vec(board, color.opposite(), depth - 1, true);
+    }
+    let mut count = 0;
+    for square in moves {
+        let flips = board.flips(square, color);
+        board.make(square, flips, color);
+        count += perft_vec(board, color.opposite(), depth - 1, false);
+        board.unmake(square, flips, color);
+    }
+    count
+}
+
+/// `moves` (ビットマスクのイテレータ) で合法手を列挙する perft
+fn perft_iter(board: &mut Board, color: Color, depth: u32, prev_is_pass: bool) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let moves = board.moves(color);
+    if moves.len() == 0 {
+        if prev_is_pass {
+            return 1;
+        }
+        return perft_iter(board, color.opposite(), depth - 1, true);
+    }
+    let mut count = 0;
+    for square in moves {
+        let flips = board.flips(square, color);
+        board.make(square, flips, color);
+        count += perft_iter(board, color.opposite(), depth - 1, false);
+        board.unmake(square, flips, color);
+    }
+    count
+}
+
+/// 乱数で終局まで打つ (xorshift で合法手を選ぶ)
+fn playouts(n: u32, use_vec: bool) -> u64 {
+    let mut seed = 0x2545f4914f6cdd1d_u64;
+    let mut nodes = 0;
+    for _ in 0..n {
+        let mut board = Board::new();
+        let mut color = Color::Black;
+        let mut is_passed = false;
+        loop {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let square = if use_vec {
+                let moves = board.valid_moves(color);
+                if moves.is_empty() {
+                    None
+                } else {
+                    Some(moves[seed as usize % moves.len()])
+                }
+            } else {
+                let can_put = board.can_put(color);
+                if can_put == 0 {
+                    None
+                } else {
+                    let k = seed as usize % bit_count(can_put) as usize;
+                    board.moves(color).nth(k)
+                }
+            };
+            match square {
+                Some(square) => {
+                    let flips = board.flips(square, color);
+                    board.make(square, flips, color);
+                    is_passed = false;
+                }
+                None if is_passed => break,
+                None => is_passed = true,
+            }
+            nodes += 1;
+            color = color.opposite();
+        }
+    }
+    nodes
+}
+
+fn report(name: &str, f: impl Fn() -> u64) -> f64 {
+    let start = Instant::now();
+    let nodes = f();
+    let elapsed = start.elapsed().as_secs_f64();
+    let nps = nodes as f64 / elapsed;
+    println!("{name:<16} {nodes:>12} nodes {elapsed:>8.3}s {nps:>14.0} nodes/s");
+    nps
+}
+
+fn main() {
+    let depth = 9;
+    let vec = report("perft vec", || {
+        perft_vec(&mut Board::new(), Color::Black, depth, false)
+    });
+    let iter = report("perft iter", || {
+        perft_iter(&mut Board::new(), Color::Black, depth, false)
+    });
+    println!("perft speedup: {:.2}x", iter / vec);
+
+    let vec = report("playout vec", || playouts(100_000, true));
+    let iter = report("playout iter", || playouts(100_000, false));
+    println!("playout speedup: {:.2}x", iter / vec);
+}
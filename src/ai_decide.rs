@@ -1,72 +1,185 @@
 use super::proto::{Color, Move};
-use crate::depth_first_search;
-use crate::monte;
-use crate::use_book;
-use std::collections::HashMap;
 use crate::bit_othello::Board;
+use crate::depth_first_search::{self, Proof, Solution};
+use crate::eval;
+use crate::game::Game;
+use crate::search_stats::SearchStats;
+use crate::square::Square;
+use crate::{midgame, monte, probcut, use_book};
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use thiserror::Error;
 
-pub fn decide(
-    board: &Board,
-    player_color: Color,
-    kihu: &Vec<Move>,
+/// 石差の完全読みを試みる石数 (読み切れなければ時間切れで打ち切る)
+const PERFECT_READ_STONES: u64 = 44;
+
+/// 読み切りを始める石数 (これより少なければ中盤の探索で打つ)
+const ENDGAME_STONES: u64 = 43;
+
+/// 読み切りの後に中盤の探索をするのに必要な残り時間 (これより少なければ読み切りの手を打つ)
+const MIN_FALLBACK_MS: i32 = 10;
+
+/// 定石の後、読み切りの前に使う探索
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Engine {
+    /// モンテカルロ木探索
+    #[default]
+    Monte,
+    /// パターン評価関数を使った反復深化の PVS
+    Pvs,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("unknown engine `{0}` (expected `monte` or `pvs`)")]
+pub struct ParseEngineError(pub String);
+
+impl FromStr for Engine {
+    type Err = ParseEngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "monte" => Ok(Self::Monte),
+            "pvs" => Ok(Self::Pvs),
+            _ => Err(ParseEngineError(s.to_string())),
+        }
+    }
+}
+
+impl Display for Engine {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Monte => write!(f, "monte"),
+            Self::Pvs => write!(f, "pvs"),
+        }
+    }
+}
+
+/// 局面の段階ごとに使う探索の設定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    /// 石数が `midgame_stones` より少ないときに使う探索
+    pub opening: Engine,
+    /// 石数が `midgame_stones` 以上で、読み切りを始める前に使う探索
+    pub midgame: Engine,
+    pub midgame_stones: u64,
+    /// 1手にかける時間
+    pub time_ms: i32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            opening: Engine::Monte,
+            midgame: Engine::Monte,
+            midgame_stones: 24,
+            time_ms: 1400,
+        }
+    }
+}
+
+impl Config {
+    /// 石数 `stones` の局面で使う探索
+    pub fn engine(&self, stones: u64) -> Engine {
+        if stones < self.midgame_stones {
+            self.opening
+        } else {
+            self.midgame
+        }
+    }
+}
+
+/// `monte` や `pvs` のように全ての段階で同じ探索を使うか、
+/// `monte,pvs` のように序盤と中盤の探索を `,` で区切って書きます。
+impl FromStr for Config {
+    type Err = ParseEngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (opening, midgame) = match s.split_once(',') {
+            Some((opening, midgame)) => (opening.parse()?, midgame.parse()?),
+            None => {
+                let engine = s.parse()?;
+                (engine, engine)
+            }
+        };
+        Ok(Self {
+            opening,
+            midgame,
+            ..Self::default()
+        })
+    }
+}
+
+impl Display for Config {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.opening == self.midgame {
+            write!(f, "{}", self.opening)
+        } else {
+            write!(f, "{},{}", self.opening, self.midgame)
+        }
+    }
+}
+
+/// 打つ手と、それを決めた探索の統計を返します (定石や合法手が1つの場合は探索しません)。
+pub fn decide(game: &Game, book_dict: &HashMap<String, String>) -> (Move, SearchStats) {
+    decide_with(game, book_dict, &Config::default())
+}
+
+/// [`decide`] と同じですが、段階ごとに使う探索を `config` で選びます。
+pub fn decide_with(
+    game: &Game,
     book_dict: &HashMap<String, String>,
-) -> Move {
-    let time_to_decide = 1400;
+    config: &Config,
+) -> (Move, SearchStats) {
+    let board = game.board();
+    let player_color = game.side_to_move();
+    let kihu = game.kihu();
+    let time_to_decide = config.time_ms;
     let moves = board.valid_moves(player_color);
     if moves.is_empty() {
-        Move::Pass
+        (Move::Pass, SearchStats::default())
     } else if moves.len() == 1 {
-        Move::Mv {
-            x_ah: moves[0].0 as u32,
-            y_18: moves[0].1 as u32,
-        }
+        (Move::Mv(moves[0]), SearchStats::default())
     } else {
-        match use_book::decide(&kihu, &book_dict) {
+        match use_book::decide(kihu, book_dict) {
             Some(mv) if board.check_valid_move(mv, player_color) => {
                 println!("I use book");
-                mv
+                (mv, SearchStats::default())
             }
             _ => {
-                if board.sum_stones() <= 42 {
-                    monte::decide(board, player_color, time_to_decide)
+                if board.sum_stones() < ENDGAME_STONES {
+                    search_midgame(config, board, player_color, time_to_decide, None)
                 } else {
-                    let max_eval;
-                    let mv;
-                    (mv, max_eval) =
-                        depth_first_search::decide(board, player_color, time_to_decide);
-                    if max_eval == None {
-                        // 読みきれなかった場合
-                        println!("I failed search all moves");
-                        monte::decide(board, player_color, time_to_decide)
-                    } else if max_eval == Some(-1) {
-                        // 負け確定の場合
-                        println!("I will lose");
-                        let (mv_second, max_eval_second) = if board.sum_stones() >= 46 {
-                            depth_first_search::perfect_read(board, player_color, time_to_decide)
-                        } else {
-                            (Move::GiveUp, None)
-                        };
-                        if max_eval_second == None {
-                            monte::decide(board, player_color, time_to_decide)
-                        } else {
-                            println!("predicted diff stones: {}", max_eval_second.unwrap());
-                            mv_second
-                        }
+                    let solution = if board.sum_stones() >= PERFECT_READ_STONES {
+                        depth_first_search::perfect_read(board, player_color, time_to_decide)
                     } else {
-                        // 引き分けか勝ち確定の場合
-                        println!("You will lose");
-                        // let (mv_second, max_eval_second) =
-                        //     depth_first_search::perfect_read(board, player_color, time_to_decide);
-                        let (mv_second, max_eval_second) = if board.sum_stones() >= 46 {
-                            depth_first_search::perfect_read(board, player_color, time_to_decide)
-                        } else {
-                            (Move::GiveUp, None)
-                        };
-                        if max_eval_second == None {
-                            mv
-                        } else {
-                            println!("predicted diff stones: {}", max_eval_second.unwrap());
-                            mv_second
+                        depth_first_search::decide(board, player_color, time_to_decide)
+                    };
+                    match solution.proof {
+                        Proof::Exact => {
+                            let pv: Vec<String> = solution.pv.iter().map(Move::to_string).collect();
+                            println!("predicted diff stones: {}", solution.value);
+                            println!("pv: {}", pv.join(" "));
+                            (solution.best_move, solution.stats)
+                        }
+                        // 負け確定の場合は、相手の間違いに期待してモンテカルロ木探索で打つ
+                        Proof::Wld if solution.value < 0 => {
+                            println!("I will lose");
+                            with_fallback(config, solution, board, player_color)
+                        }
+                        Proof::Wld => {
+                            // 引き分けか勝ち確定の場合
+                            println!("You will lose");
+                            (solution.best_move, solution.stats)
+                        }
+                        // 読みきれなかったが、引き分け以上が確定した手がある場合
+                        Proof::Unproven if solution.value >= 0 => {
+                            println!("I failed search all moves, but I won't lose");
+                            (solution.best_move, solution.stats)
+                        }
+                        Proof::Unproven => {
+                            println!("I failed search all moves");
+                            with_fallback(config, solution, board, player_color)
                         }
                     }
                 }
@@ -74,3 +187,54 @@ pub fn decide(
         }
     }
 }
+
+/// `config` でその段階に選んだ探索で手を決めます。
+///
+/// PVS は `hint` があればその手から読みます (モンテカルロ木探索は使いません)。
+fn search_midgame(
+    config: &Config,
+    board: &Board,
+    color: Color,
+    time_to_decide: i32,
+    hint: Option<Square>,
+) -> (Move, SearchStats) {
+    match config.engine(board.sum_stones()) {
+        Engine::Monte => monte::decide(board, color, time_to_decide),
+        Engine::Pvs => {
+            let result = midgame::decide_with(
+                board,
+                color,
+                time_to_decide,
+                eval::weights(),
+                probcut::installed(),
+                hint,
+            );
+            println!("eval: {:+.2}", result.value as f64 / eval::SCALE as f64);
+            (result.best_move, result.stats)
+        }
+    }
+}
+
+/// 読み切りの後に、1手の時間の残りで中盤の探索をして打つ手を決め、両方の統計を足し合わせます。
+///
+/// 中盤の探索は読み切りで最善だった手から読みます。残り時間がなければ読み切りの手を打ちます。
+fn with_fallback(
+    config: &Config,
+    solution: Solution,
+    board: &Board,
+    color: Color,
+) -> (Move, SearchStats) {
+    let mut stats = solution.stats;
+    let remaining = config.time_ms - stats.elapsed.as_millis() as i32;
+    let hint = match solution.best_move {
+        Move::Mv(square) => Some(square),
+        _ => None,
+    };
+    if remaining < MIN_FALLBACK_MS && hint.is_some() {
+        return (solution.best_move, stats);
+    }
+    let (mv, midgame_stats) =
+        search_midgame(config, board, color, remaining.max(MIN_FALLBACK_MS), hint);
+    stats.merge(&midgame_stats);
+    (mv, stats)
+}
@@ -1,14 +1,32 @@
 use super::proto::{Color, Move};
-#[cfg(test)]
-use rand::Rng;
+use crate::square::Square;
 use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use thiserror::Error;
+
+/// 着手で裏返る石のマスク
+pub type Flips = u64;
+
+#[derive(Debug, Error, PartialEq, Clone, Copy)]
+pub enum IllegalMove {
+    #[error("{0} is already occupied")]
+    Occupied(Move),
+    #[error("{0} flips no stones")]
+    NoFlips(Move),
+    #[error("cannot pass while there are valid moves")]
+    PassWithMoves,
+    #[error("GIVEUP is not a move")]
+    GiveUp,
+    #[error("the game is already over")]
+    GameOver,
+}
 
 pub struct InitGame {
     pub opponent_name: String,
     pub assigned_time_ms: i32,
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Board {
     pub black: u64,
     pub white: u64,
@@ -16,6 +34,37 @@ pub struct Board {
 
 pub type Pos = (usize, usize);
 
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum ParsePositionError {
+    #[error("expected 64 squares, got {0}")]
+    Length(usize),
+    #[error("invalid square `{0}` (expected X, O or -)")]
+    Square(char),
+    #[error("invalid side to move `{0}` (expected X or O)")]
+    Side(String),
+}
+
+/// 盤面と手番の組です。
+///
+/// `A1, B1, ..., H1, A2, ..., H8` の順に黒を `X`、白を `O`、空きを `-` で並べた
+/// 64文字の後に、空白を挟んで手番 (`X` か `O`) を書いた形式で読み書きできます。
+///
+/// ```
+/// use fl_reversi_rs::bit_othello::{Board, Position};
+/// use fl_reversi_rs::Color;
+///
+/// let s = "---------------------------OX------XO--------------------------- X";
+/// let position: Position = s.parse().unwrap();
+/// assert_eq!(position.board.count_stones(), Board::new().count_stones());
+/// assert_eq!(position.color, Color::Black);
+/// assert_eq!(position.to_string(), s);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Position {
+    pub board: Board,
+    pub color: Color,
+}
+
 impl Board {
     pub fn new() -> Self {
         Self {
@@ -24,16 +73,9 @@ impl Board {
         }
     }
 
-    fn can_put(&self, color: Color) -> u64 {
-        let p;
-        let o;
-        if color == Color::Black {
-            p = self.black;
-            o = self.white;
-        } else {
-            p = self.white;
-            o = self.black;
-        };
+    /// `color` が打てるマスのビットマスク
+    pub fn can_put(&self, color: Color) -> u64 {
+        let (p, o) = self.player_opponent(color);
         let horizontal = o & 0x7e7e7e7e7e7e7e7e;
         let vertical = o & 0x00ffffffffffff00;
         let allside = o & 0x007e7e7e7e7e7e00;
@@ -128,48 +170,105 @@ impl Board {
         }
     }
 
-    pub fn do_move(&mut self, m: Move, color: Color) {
-        let p;
-        let o;
-        if color == Color::Black {
-            p = &mut self.black;
-            o = &mut self.white;
-        } else {
-            p = &mut self.white;
-            o = &mut self.black;
-        };
-        if let Move::Mv { x_ah, y_18 } = m {
-            let x = x_ah as usize;
-            let y = y_18 as usize;
-            let pos = (x - 1) * 8 + y - 1;
-            let pos_bit = 1 << pos;
-            let mut rev_temp;
-            let mut rev = 0;
-            for i in 0..8 {
-                rev_temp = 0;
-                let mut mask = transfer(pos_bit, i);
-                while mask != 0 && (mask & *o) != 0 {
-                    rev_temp |= mask;
-                    mask = transfer(mask, i);
+    /// `color` が `square` に打ったときに裏返る石のマスクを返します。
+    /// 盤面は変更しません。
+    pub fn flips(&self, square: Square, color: Color) -> u64 {
+        let (p, o) = self.player_opponent(color);
+        let pos_bit = square.bit();
+        let mut rev = 0;
+        for i in 0..8 {
+            let mut rev_temp = 0;
+            let mut mask = transfer(pos_bit, i);
+            while mask != 0 && (mask & o) != 0 {
+                rev_temp |= mask;
+                mask = transfer(mask, i);
+            }
+            if (mask & p) != 0 {
+                rev |= rev_temp;
+            }
+        }
+        rev
+    }
+
+    /// `flips` で求めたマスクを使って `square` に石を置きます。
+    pub fn make(&mut self, square: Square, flips: u64, color: Color) {
+        let (p, o) = self.player_opponent_mut(color);
+        *p ^= square.bit() | flips;
+        *o ^= flips;
+    }
+
+    /// `make` で打った手を元に戻します。
+    pub fn unmake(&mut self, square: Square, flips: u64, color: Color) {
+        // xor なので make と同じ操作で元に戻る
+        self.make(square, flips, color);
+    }
+
+    /// 合法手かどうかを確かめてから打ちます。
+    /// 合法手なら裏返った石のマスクを返し、そうでなければ盤面を変えずにエラーを返します。
+    pub fn try_move(&mut self, m: Move, color: Color) -> Result<Flips, IllegalMove> {
+        match m {
+            Move::Mv(square) => {
+                if (self.black | self.white) & square.bit() != 0 {
+                    return Err(IllegalMove::Occupied(m));
                 }
-                if (mask & *p) != 0 {
-                    rev |= rev_temp;
+                let flips = self.flips(square, color);
+                if flips == 0 {
+                    return Err(IllegalMove::NoFlips(m));
                 }
+                self.make(square, flips, color);
+                Ok(flips)
             }
-            *p ^= pos_bit | rev;
-            *o ^= rev;
+            Move::Pass if self.can_put(color) != 0 => Err(IllegalMove::PassWithMoves),
+            Move::Pass => Ok(0),
+            Move::GiveUp => Err(IllegalMove::GiveUp),
         }
     }
 
-    pub fn valid_moves(&self, color: Color) -> Vec<Pos> {
-        let mut ret = vec![];
-        let can_put = self.can_put(color);
-        for i in 0..64 {
-            if can_put & (1 << i) != 0 {
-                ret.push((i / 8 + 1, i % 8 + 1));
-            }
+    /// 合法手であることを確かめずに打ちます。探索など合法手だと分かっている場合に使います。
+    pub fn do_move(&mut self, m: Move, color: Color) {
+        if let Move::Mv(square) = m {
+            let flips = self.flips(square, color);
+            self.make(square, flips, color);
+        }
+    }
+
+    /// `(手番側の石, 相手の石)`
+    pub(crate) fn player_opponent(&self, color: Color) -> (u64, u64) {
+        if color == Color::Black {
+            (self.black, self.white)
+        } else {
+            (self.white, self.black)
+        }
+    }
+
+    fn player_opponent_mut(&mut self, color: Color) -> (&mut u64, &mut u64) {
+        if color == Color::Black {
+            (&mut self.black, &mut self.white)
+        } else {
+            (&mut self.white, &mut self.black)
+        }
+    }
+
+    /// `color` が打てるマスを、メモリ確保なしで順に返すイテレータ
+    pub fn moves(&self, color: Color) -> Squares {
+        Squares(self.can_put(color))
+    }
+
+    pub fn valid_moves(&self, color: Color) -> Vec<Square> {
+        self.moves(color).collect()
+    }
+
+    /// `color` の手番の局面で実際に打つ側
+    ///
+    /// 打てる手がなければパスして相手の番になり、両者とも打てなければ終局で `None` を返します。
+    pub fn side_to_play(&self, color: Color) -> Option<Color> {
+        if self.can_put(color) != 0 {
+            Some(color)
+        } else if self.can_put(color.opposite()) != 0 {
+            Some(color.opposite())
+        } else {
+            None
         }
-        ret
     }
 
     pub fn count_stones(&self) -> (u64, u64) {
@@ -211,14 +310,44 @@ impl Board {
         }
     }
 
+    /// 深さ `depth` までの末端局面の数を数えます (perft)。
+    ///
+    /// 探索と同じく、打てる手がない場合はパスを1手として数え、
+    /// 両者が続けてパスした局面は終局としてそこで1つの末端になります。
+    pub fn perft(&self, color: Color, depth: u32) -> u64 {
+        self.clone().perft_inner(color, depth, false)
+    }
+
+    fn perft_inner(&mut self, color: Color, depth: u32, prev_is_pass: bool) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let can_put = self.can_put(color);
+        if can_put == 0 {
+            if prev_is_pass {
+                return 1;
+            }
+            return self.perft_inner(color.opposite(), depth - 1, true);
+        }
+        if depth == 1 {
+            return bit_count(can_put);
+        }
+        let mut count = 0;
+        for square in Squares(can_put) {
+            let flips = self.flips(square, color);
+            self.make(square, flips, color);
+            count += self.perft_inner(color.opposite(), depth - 1, false);
+            self.unmake(square, flips, color);
+        }
+        count
+    }
+
     pub fn check_valid_move(&self, m: Move, color: Color) -> bool {
-        let valid_moves = self.valid_moves(color);
-        if valid_moves.is_empty() && m == Move::Pass {
+        let can_put = self.can_put(color);
+        if can_put == 0 && m == Move::Pass {
             true
-        } else if let Move::Mv { x_ah, y_18 } = m {
-            let x = x_ah as usize;
-            let y = y_18 as usize;
-            valid_moves.contains(&(x, y))
+        } else if let Move::Mv(square) = m {
+            can_put & square.bit() != 0
         } else {
             false
         }
@@ -236,14 +365,41 @@ pub fn bit_count(x: u64) -> u64 {
     x & 0x7f
 }
 
-pub fn get_corner_list(valid_moves: &Vec<Pos>) -> Vec<Pos> {
-    let mut ret = vec![];
-    for &m in valid_moves {
-        if (m.0 == 1 || m.0 == 8) && (m.1 == 1 || m.1 == 8) {
-            ret.push(m);
+/// 四隅 (A1, A8, H1, H8) のマスク
+pub const CORNERS: u64 = 0x8100000000000081;
+
+/// ビットマスクの立っているマスをビット番号の小さい順に返すイテレータ
+#[derive(Debug, Clone, Copy)]
+pub struct Squares(pub u64);
+
+impl Iterator for Squares {
+    type Item = Square;
+
+    #[inline]
+    fn next(&mut self) -> Option<Square> {
+        if self.0 == 0 {
+            None
+        } else {
+            let square = Square::from_index_unchecked(self.0.trailing_zeros() as usize);
+            self.0 &= self.0 - 1;
+            Some(square)
         }
     }
-    ret
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.0.count_ones() as usize;
+        (n, Some(n))
+    }
+}
+
+impl ExactSizeIterator for Squares {}
+
+pub fn get_corner_list(valid_moves: &[Square]) -> Vec<Square> {
+    valid_moves
+        .iter()
+        .copied()
+        .filter(|square| square.bit() & CORNERS != 0)
+        .collect()
 }
 
 fn transfer(put: u64, dir: u32) -> u64 {
@@ -260,6 +416,98 @@ fn transfer(put: u64, dir: u32) -> u64 {
     }
 }
 
+fn parse_color(s: &str) -> Result<Color, ParsePositionError> {
+    match s {
+        "X" | "x" | "*" | "B" | "b" => Ok(Color::Black),
+        "O" | "o" | "W" | "w" => Ok(Color::White),
+        _ => Err(ParsePositionError::Side(s.to_string())),
+    }
+}
+
+fn color_char(color: Color) -> char {
+    if color == Color::Black {
+        'X'
+    } else {
+        'O'
+    }
+}
+
+impl Board {
+    /// 64文字の形式で盤面を書き出します。
+    pub fn to_compact_string(&self) -> String {
+        let mut ret = String::with_capacity(64);
+        for row in 0..8 {
+            for col in 0..8 {
+                let bit = 1 << (col * 8 + row);
+                ret.push(if self.black & bit != 0 {
+                    'X'
+                } else if self.white & bit != 0 {
+                    'O'
+                } else {
+                    '-'
+                });
+            }
+        }
+        ret
+    }
+}
+
+impl FromStr for Board {
+    type Err = ParsePositionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let squares: Vec<char> = s.chars().filter(|c| !c.is_whitespace()).collect();
+        if squares.len() != 64 {
+            return Err(ParsePositionError::Length(squares.len()));
+        }
+        let mut board = Board { black: 0, white: 0 };
+        for (i, &c) in squares.iter().enumerate() {
+            // 文字列は行ごと、ビットは列ごとに並んでいる
+            let bit = 1 << ((i % 8) * 8 + i / 8);
+            match c {
+                'X' | 'x' | '*' => board.black |= bit,
+                'O' | 'o' => board.white |= bit,
+                '-' | '.' => {}
+                _ => return Err(ParsePositionError::Square(c)),
+            }
+        }
+        Ok(board)
+    }
+}
+
+impl FromStr for Position {
+    type Err = ParsePositionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let mut squares = String::with_capacity(64);
+        let mut chars = s.char_indices().filter(|(_, c)| !c.is_whitespace());
+        for (_, c) in chars.by_ref().take(64) {
+            squares.push(c);
+        }
+        let board = squares.parse()?;
+        let side = match chars.next() {
+            Some((i, _)) => s[i..].trim(),
+            None => return Err(ParsePositionError::Side(String::new())),
+        };
+        Ok(Position {
+            board,
+            color: parse_color(side)?,
+        })
+    }
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {}",
+            self.board.to_compact_string(),
+            color_char(self.color)
+        )
+    }
+}
+
 impl Default for Board {
     fn default() -> Self {
         Self::new()
@@ -301,6 +549,36 @@ impl Display for Board {
 //     }
 // }
 
+/// 初期局面からランダムに打ち進めた、空きマスが `empties` 個の局面と手番 (テスト用)
+///
+/// 手番側はパスしかできないこともあります。
+#[cfg(test)]
+pub(crate) fn random_position(empties: u64) -> (Board, Color) {
+    use rand::Rng;
+    let mut rng = rand::thread_rng();
+    loop {
+        let mut board = Board::new();
+        let mut color = Color::Black;
+        let mut passed = false;
+        while 64 - board.sum_stones() > empties {
+            let moves = board.valid_moves(color);
+            if moves.is_empty() {
+                if passed {
+                    break;
+                }
+                passed = true;
+            } else {
+                passed = false;
+                board.do_move(Move::Mv(moves[rng.gen_range(0..moves.len())]), color);
+            }
+            color = color.opposite();
+        }
+        if 64 - board.sum_stones() == empties {
+            return (board, color);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -332,7 +610,11 @@ mod tests {
             white: 0x0000001008000000,
         };
         let can_put = board.can_put(Color::Black);
-        println!("canput{:016x}", can_put);
+        // C4, D3, E6, F5
+        assert_eq!(can_put, 1 << 19 | 1 << 26 | 1 << 37 | 1 << 44);
+        let can_put = board.can_put(Color::White);
+        // C5, D6, E3, F4
+        assert_eq!(can_put, 1 << 20 | 1 << 29 | 1 << 34 | 1 << 43);
     }
 
     // #[test]
@@ -368,5 +650,130 @@ mod tests {
     //     println!("{:?}", board.win_or_lose(Color::Black));
     // }
 
+    fn sq(s: &str) -> Square {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_moves_iterator() {
+        let board = Board::new();
+        let squares: Vec<usize> = board.moves(Color::Black).map(Square::index).collect();
+        assert_eq!(squares, vec![19, 26, 37, 44]);
+        assert_eq!(board.moves(Color::Black).len(), 4);
+        let names: Vec<String> = board.moves(Color::Black).map(|s| s.to_string()).collect();
+        assert_eq!(names, vec!["C4", "D3", "E6", "F5"]);
+        assert_eq!(Squares(0).next(), None);
+        let corners: Vec<usize> = Squares(CORNERS).map(Square::index).collect();
+        assert_eq!(corners, vec![0, 7, 56, 63]);
+    }
+
+    #[test]
+    fn test_side_to_play() {
+        assert_eq!(Board::new().side_to_play(Color::Black), Some(Color::Black));
+        // 白は隅の黒を挟めないのでパスし、黒が C1 に打つ
+        let board = Board {
+            black: sq("A1").bit(),
+            white: sq("B1").bit(),
+        };
+        assert_eq!(board.side_to_play(Color::White), Some(Color::Black));
+        assert_eq!(board.side_to_play(Color::Black), Some(Color::Black));
+        let board = Board {
+            black: sq("A1").bit(),
+            white: 0,
+        };
+        assert_eq!(board.side_to_play(Color::Black), None);
+    }
+
+    #[test]
+    fn test_flips_make_unmake() {
+        let mut board = Board::new();
+        // C4 に黒が打つと D4 が裏返る
+        let square = sq("C4");
+        let flips = board.flips(square, Color::Black);
+        assert_eq!(flips, sq("D4").bit());
+        assert_eq!(board.black, Board::new().black);
+
+        board.make(square, flips, Color::Black);
+        assert_eq!(board.count_stones(), (4, 1));
+        board.unmake(square, flips, Color::Black);
+        assert_eq!(board, Board::new());
+
+        // 置けない場所では何も裏返らない
+        assert_eq!(board.flips(sq("A1"), Color::Black), 0);
+    }
+
+    #[test]
+    fn test_try_move() {
+        let mut board = Board::new();
+        let d4 = Move::Mv(sq("D4"));
+        let a1 = Move::Mv(sq("A1"));
+        assert_eq!(
+            board.try_move(d4, Color::Black),
+            Err(IllegalMove::Occupied(d4))
+        );
+        assert_eq!(
+            board.try_move(a1, Color::Black),
+            Err(IllegalMove::NoFlips(a1))
+        );
+        assert_eq!(
+            board.try_move(Move::Pass, Color::Black),
+            Err(IllegalMove::PassWithMoves)
+        );
+        assert_eq!(
+            board.try_move(Move::GiveUp, Color::Black),
+            Err(IllegalMove::GiveUp)
+        );
+        assert_eq!(board.count_stones(), (2, 2));
+
+        let c4 = Move::Mv(sq("C4"));
+        assert_eq!(board.try_move(c4, Color::Black), Ok(sq("D4").bit()));
+        assert_eq!(board.count_stones(), (4, 1));
+
+        // 白が全滅したので白はパスしかできない
+        let mut board = Board {
+            black: 0x0000001818000000,
+            white: 0,
+        };
+        assert_eq!(board.try_move(Move::Pass, Color::White), Ok(0));
+    }
+
+    #[test]
+    fn test_position_round_trip() {
+        let s = "--XXXXX--OOOXX-O-OOOXXOX-OXOXOXXOXXXOXXX--XOXOXX-XXXOOO--OOOOO-- X";
+        let position: Position = s.parse().unwrap();
+        assert_eq!(position.to_string(), s);
+        assert_eq!(position.color, Color::Black);
+        // C1 は黒, B2 は白
+        assert_ne!(position.board.black & sq("C1").bit(), 0);
+        assert_ne!(position.board.white & sq("B2").bit(), 0);
+
+        let position: Position = format!("{} O", Board::new().to_compact_string())
+            .parse()
+            .unwrap();
+        assert_eq!(position.board, Board::new());
+        assert_eq!(position.color, Color::White);
+    }
+
+    #[test]
+    fn test_position_parse_error() {
+        let board = Board::new().to_compact_string();
+        assert_eq!(
+            "XO".parse::<Board>().unwrap_err(),
+            ParsePositionError::Length(2)
+        );
+        assert_eq!(
+            board.replace('X', "Z").parse::<Board>().unwrap_err(),
+            ParsePositionError::Square('Z')
+        );
+        assert_eq!(
+            board.parse::<Position>().unwrap_err(),
+            ParsePositionError::Side(String::new())
+        );
+        assert_eq!(
+            format!("{board} Q").parse::<Position>().unwrap_err(),
+            ParsePositionError::Side("Q".to_string())
+        );
+    }
+
     // 他のテスト関数をここに追加
 }
@@ -45,15 +45,8 @@ rule! {
         if input == "GIVEUP" {
             return Ok(Move::GiveUp);
         }
-        if input.len() == 2 {
-            let mut chars = input.chars();
-            let x = chars.next().unwrap();
-            let y = chars.next().unwrap();
-            if matches!(x, 'A'..='H') && matches!(y, '1'..='8') {
-                let x_ah = x as u32 - 'A' as u32 + 1;
-                let y_18 = y as u32 - '1' as u32 + 1;
-                return Ok(Move::Mv { x_ah, y_18 });
-            }
+        if let Ok(square) = input.parse() {
+            return Ok(Move::Mv(square));
         }
     }
 
@@ -1,343 +1,887 @@
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use super::proto::{Color, Move};
-use crate::bit_othello::Board;
+#[cfg(test)]
+use crate::bit_othello::Squares;
+use crate::bit_othello::{bit_count, Board};
+use crate::eval::{Evaluator, Mobility};
+use crate::last_moves;
+use crate::move_order::{self, MoveOrdering};
+use crate::search_stats::{SearchCounter, SearchStats};
+use crate::transposition::{Bound, Entry, TranspositionTable};
+use crate::zobrist;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::Instant;
+
+/// 石差の最小値より小さい値 (fail-soft の初期値)
+pub(crate) const SCORE_MIN: i32 = -65;
+
+/// 置換表を使う空きマス数 (これより浅い局面は読み直した方が速い)
+const TT_MIN_EMPTIES: u64 = 7;
+
+/// 読み切りの探索窓
+///
+/// 返り値は手番側から見た最終的な石差で、窓の外側の値は上界・下界になります (fail-soft)。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    /// 石差を正確に求める
+    Exact,
+    /// 勝ち (正)・引き分け (0)・負け (負) だけを求める
+    Wld,
+    /// `n` 石差以上で勝てるかどうか (返り値が `n` 以上なら勝てる)
+    AtLeast(i32),
+}
 
-fn reverse_value(value: Option<i32>) -> Option<i32> {
-    match value {
-        Some(x) => Some(-x),
-        None => None,
+impl Window {
+    /// `(alpha, beta)` の組
+    pub fn bounds(self) -> (i32, i32) {
+        match self {
+            Self::Exact => (-64, 64),
+            Self::Wld => (-1, 1),
+            Self::AtLeast(n) => (n - 1, n),
+        }
     }
 }
 
-pub fn calc_by_dfs(
-    board: &Board,
-    color: Color,
-    prev_move: Option<Move>,
-    start_time: &std::time::Instant,
-    assigned_time_ms: i32,
-    stone_num: u64,
-) -> Option<i32> {
-    if start_time.elapsed().as_millis() > assigned_time_ms as u128 {
-        return None; // Timeout
+/// 終盤の完全読みを行う探索器です。
+///
+/// fail-soft の negamax alpha-beta で、窓 `(alpha, beta)` の中の値は正確な石差、
+/// `alpha` 以下なら上界、`beta` 以上なら下界になります。
+/// 読んだ結果は置換表に残し、同じ表を使う他の探索器 (他のスレッド) と共有します。
+pub struct Solver<'a> {
+    counter: SearchCounter,
+    tt: &'a TranspositionTable,
+    ordering: MoveOrdering,
+    evaluator: &'a dyn Evaluator,
+}
+
+impl<'a> Solver<'a> {
+    pub fn new(tt: &'a TranspositionTable, start_time: Instant, assigned_time_ms: i32) -> Self {
+        Self {
+            counter: SearchCounter::new(start_time, assigned_time_ms),
+            tt,
+            ordering: MoveOrdering::default(),
+            evaluator: &Mobility,
+        }
     }
-    let moves = board.valid_moves(color);
-    if moves.is_empty() {
-        if prev_move == Some(Move::Pass) {
-            Some(board.win_or_lose(color))
-        } else {
-            let next_color = color.opposite();
-            reverse_value(calc_by_dfs(
+
+    /// 手の並べ方を変えます (比較用)。
+    pub fn with_ordering(mut self, ordering: MoveOrdering) -> Self {
+        self.ordering = ordering;
+        self
+    }
+
+    /// `MoveOrdering::Evaluation` で使う評価関数を変えます (既定は着手可能数の差)。
+    pub fn with_evaluator(mut self, evaluator: &'a dyn Evaluator) -> Self {
+        self.evaluator = evaluator;
+        self
+    }
+
+    /// 探索したノード数
+    pub fn nodes(&self) -> u64 {
+        self.counter.nodes
+    }
+
+    /// この探索器の統計 (経過時間と深さは呼び出し側で埋めます)
+    pub fn stats(&self) -> SearchStats {
+        self.counter.stats()
+    }
+
+    /// `color` の手番で `(alpha, beta)` の窓で読み切ります。時間切れなら `None` を返します。
+    /// `passed` は直前の手がパスだったかどうかです。
+    pub fn solve(
+        &mut self,
+        board: &mut Board,
+        color: Color,
+        alpha: i32,
+        beta: i32,
+        passed: bool,
+    ) -> Option<i32> {
+        let hash = zobrist::hash_with_color(board, color);
+        self.search(board, color, hash, alpha, beta, passed)
+    }
+
+    /// `hash` は `board` と `color` のZobristハッシュ
+    fn search(
+        &mut self,
+        board: &mut Board,
+        color: Color,
+        hash: u64,
+        alpha: i32,
+        beta: i32,
+        passed: bool,
+    ) -> Option<i32> {
+        if self.counter.check_timeout() {
+            return None; // Timeout
+        }
+        let empties = 64 - board.sum_stones();
+        if empties <= last_moves::MAX_EMPTIES {
+            return Some(last_moves::solve(
                 board,
-                next_color,
-                Some(Move::Pass),
-                start_time,
-                assigned_time_ms,
-                stone_num,
-            ))
+                color,
+                alpha,
+                beta,
+                passed,
+                &mut self.counter.nodes,
+            ));
         }
-    } else {
-        let mut max_eval = -64;
-        if stone_num < 60 {
-            let mut board_list: Vec<(Board, i32, Move)> = vec![];
-            for m in moves {
-                let mut board = board.clone();
-                let selected_move = Move::Mv {
-                    x_ah: m.0 as u32,
-                    y_18: m.1 as u32,
-                };
-                board.do_move(selected_move, color);
-                let canput_diff = board.canput_diff(color);
-                board_list.push((board, canput_diff, selected_move));
-                // let ret = reverse_value(calc_by_dfs(
-                //     &board,
-                //     color.opposite(),
-                //     Some(selected_move),
-                //     start_time,
-                //     assigned_time_ms,
-                // ));
-                // if ret == None {
-                //     return None;
-                // }
-                // let ret = ret.unwrap();
-                // if ret > max_eval {
-                //     max_eval = ret;
-                // }
-                // if ret == 1 {
-                //     return Some(1);
-                // }
+        self.counter.nodes += 1;
+        let can_put = board.can_put(color);
+        if can_put == 0 {
+            if passed {
+                return Some(board.diff_stones(color));
             }
-            board_list.sort_by(|a, b| b.1.cmp(&a.1));
-            for (board, _, selected_move) in board_list {
-                let ret = reverse_value(calc_by_dfs(
-                    &board,
+            return self
+                .search(
+                    board,
                     color.opposite(),
-                    Some(selected_move),
-                    start_time,
-                    assigned_time_ms,
-                    stone_num + 1,
-                ));
-                if let Some(ret_raw) = ret {
-                    if ret_raw > max_eval {
-                        max_eval = ret_raw;
-                    }
-                    if ret_raw == 1 {
-                        return Some(1);
-                    }
-                } else {
-                    return None;
+                    hash ^ zobrist::WHITE_TO_MOVE,
+                    -beta,
+                    -alpha,
+                    true,
+                )
+                .map(|v| -v);
+        }
+
+        let use_tt = empties >= TT_MIN_EMPTIES;
+        let (mut alpha, mut beta) = (alpha, beta);
+        let mut tt_move = None;
+        if use_tt {
+            self.counter.tt.probes += 1;
+            if let Some(entry) = self.tt.probe(hash) {
+                self.counter.tt.hits += 1;
+                if let Some(value) = entry.cutoff(alpha, beta) {
+                    return Some(value);
                 }
+                match entry.bound {
+                    Bound::Lower => alpha = alpha.max(entry.score),
+                    Bound::Upper => beta = beta.min(entry.score),
+                    Bound::Exact => {}
+                }
+                tt_move = entry.best_move;
             }
-        } else {
-            for m in moves {
-                let mut board = board.clone();
-                let selected_move = Move::Mv {
-                    x_ah: m.0 as u32,
-                    y_18: m.1 as u32,
-                };
-                board.do_move(selected_move, color);
-                let ret = reverse_value(calc_by_dfs(
-                    &board,
-                    color.opposite(),
-                    Some(selected_move),
-                    start_time,
-                    assigned_time_ms,
-                    stone_num + 1,
-                ));
-                if let Some(ret_raw) = ret {
-                    if ret_raw > max_eval {
-                        max_eval = ret_raw;
-                    }
-                    if ret_raw == 1 {
-                        return Some(1);
+        }
+
+        if let Some(value) = stability_cutoff(board, color, alpha) {
+            return Some(value);
+        }
+
+        let move_list = move_order::ordered_moves(
+            board,
+            color,
+            can_put,
+            tt_move,
+            self.ordering,
+            self.evaluator,
+        );
+
+        let alpha_orig = alpha;
+        let mut best = SCORE_MIN;
+        let mut best_move = None;
+        for (square, flips) in move_list {
+            let child_hash = zobrist::update(hash, square, flips, color) ^ zobrist::WHITE_TO_MOVE;
+            board.make(square, flips, color);
+            let ret = self.search(board, color.opposite(), child_hash, -beta, -alpha, false);
+            board.unmake(square, flips, color);
+            let value = -ret?;
+            if value > best {
+                best = value;
+                best_move = Some(square);
+                if best > alpha {
+                    alpha = best;
+                    if alpha >= beta {
+                        break;
                     }
-                } else {
-                    return None;
                 }
             }
         }
+        if use_tt {
+            self.counter.tt.stores += 1;
+            self.tt.store(
+                hash,
+                Entry {
+                    score: best,
+                    bound: Bound::classify(best, alpha_orig, beta),
+                    best_move,
+                    depth: empties as u8,
+                },
+            );
+        }
+        Some(best)
+    }
+}
+
+/// 相手の確定石から石差の上界を求め、`alpha` 以下なら (これ以上読んでも窓に届かないので) 返します。
+fn stability_cutoff(board: &Board, color: Color, alpha: i32) -> Option<i32> {
+    let (black, white) = board.count_stones();
+    let opponent = if color == Color::Black { white } else { black };
+    // 相手の石が全て確定石でも上界が alpha を超えるなら、確定石を数えるまでもない
+    if 64 - 2 * opponent as i32 > alpha {
+        return None;
+    }
+    let stable = bit_count(board.stable_discs_of(color.opposite()));
+    let upper = 64 - 2 * stable as i32;
+    (upper <= alpha).then_some(upper)
+}
 
-        Some(max_eval)
+impl Drop for Solver<'_> {
+    fn drop(&mut self) {
+        self.tt.add_stats(self.counter.tt);
     }
 }
 
+/// 勝ち (1)・引き分け (0)・負け (-1) を読み切ります。
+pub fn calc_by_dfs(
+    board: &mut Board,
+    color: Color,
+    prev_move: Option<Move>,
+    start_time: &Instant,
+    assigned_time_ms: i32,
+) -> Option<i32> {
+    let (alpha, beta) = Window::Wld.bounds();
+    Solver::new(TranspositionTable::shared(), *start_time, assigned_time_ms)
+        .solve(board, color, alpha, beta, prev_move == Some(Move::Pass))
+        .map(i32::signum)
+}
+
+/// 最終的な石差を読み切ります。
 pub fn perfect_read_dfs(
-    board: &Board,
+    board: &mut Board,
     color: Color,
     prev_move: Option<Move>,
-    start_time: &std::time::Instant,
+    start_time: &Instant,
     assigned_time_ms: i32,
 ) -> Option<i32> {
-    if start_time.elapsed().as_millis() > assigned_time_ms as u128 {
-        return None; // Timeout
+    let (alpha, beta) = Window::Exact.bounds();
+    Solver::new(TranspositionTable::shared(), *start_time, assigned_time_ms).solve(
+        board,
+        color,
+        alpha,
+        beta,
+        prev_move == Some(Move::Pass),
+    )
+}
+
+/// 読み切りでどこまで証明できたか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Proof {
+    /// 時間切れで読み切れなかった (手は読み終わった手の中での最善)
+    Unproven,
+    /// 勝ち・引き分け・負けまで読み切った
+    Wld,
+    /// 石差まで読み切った
+    Exact,
+}
+
+/// 読み切りの結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Solution {
+    pub best_move: Move,
+    /// `Proof::Exact` なら手番側から見た石差、それ以外は勝ち (1)・引き分け (0)・負け (-1)。
+    /// `Proof::Unproven` では `best_move` で少なくとも得られる結果です。
+    pub value: i32,
+    pub proof: Proof,
+    /// 最善手順 (パスを含む)。`Proof::Exact` なら終局まで (時間切れの場合は途中まで)、
+    /// それ以外は `best_move` だけです。
+    pub pv: Vec<Move>,
+    pub stats: SearchStats,
+}
+
+enum RootResult {
+    /// 探索した窓の下限 `alpha` と結果
+    Done {
+        alpha: i32,
+        value: i32,
+    },
+    /// 他の手で beta カットが起きたので読まなかった
+    Skipped,
+    TimedOut,
+}
+
+/// ルートを読んだ結果
+struct RootSearch {
+    /// 読み終わった手の中での最善手と fail-soft の値
+    best: Option<(Move, i32)>,
+    /// 全ての手を読み終えた (か beta カットした) かどうか
+    complete: bool,
+    stats: SearchStats,
+}
+
+/// ルートの手を並列に読み、窓 `(alpha, beta)` の中で最善の手と値を求めます。
+///
+/// `first` があれば最初に読みます。時間切れになった手があっても、読み終わった手の結果は返します。
+fn search_root(
+    board: &Board,
+    color: Color,
+    (alpha, beta): (i32, i32),
+    first: Option<Move>,
+    start_time: Instant,
+    assigned_time_ms: i32,
+) -> RootSearch {
+    let tt = TranspositionTable::shared();
+    let can_put = board.can_put(color);
+    if can_put == 0 {
+        let mut board = board.clone();
+        let mut solver = Solver::new(tt, start_time, assigned_time_ms);
+        let value = solver.solve(&mut board, color.opposite(), -beta, -alpha, true);
+        return RootSearch {
+            best: value.map(|v| (Move::Pass, -v)),
+            complete: value.is_some(),
+            stats: solver.stats(),
+        };
     }
-    let moves = board.valid_moves(color);
-    if moves.is_empty() {
-        if prev_move == Some(Move::Pass) {
-            Some(board.diff_stones(color))
-        } else {
-            let next_color = color.opposite();
-            reverse_value(perfect_read_dfs(
-                board,
-                next_color,
-                Some(Move::Pass),
-                start_time,
-                assigned_time_ms,
-            ))
-        }
-    } else {
-        let mut max_eval = -64;
-        for m in moves {
+    let first = match first {
+        Some(Move::Mv(square)) => Some(square),
+        _ => None,
+    };
+    let moves = move_order::ordered_moves(
+        &mut board.clone(),
+        color,
+        can_put,
+        first,
+        MoveOrdering::default(),
+        &Mobility,
+    );
+
+    // 読み終わった手の最善値を共有して、後から読む手の窓を狭める
+    let shared_alpha = AtomicI32::new(alpha);
+    let results: Vec<(Move, RootResult, SearchStats)> = moves
+        .into_par_iter()
+        .map(|(square, flips)| {
+            let alpha = shared_alpha.load(Ordering::Relaxed);
+            if alpha >= beta {
+                return (
+                    Move::Mv(square),
+                    RootResult::Skipped,
+                    SearchStats::default(),
+                );
+            }
             let mut board = board.clone();
-            let selected_move = Move::Mv {
-                x_ah: m.0 as u32,
-                y_18: m.1 as u32,
+            board.make(square, flips, color);
+            let mut solver = Solver::new(tt, start_time, assigned_time_ms);
+            let result = match solver.solve(&mut board, color.opposite(), -beta, -alpha, false) {
+                Some(value) => {
+                    let value = -value;
+                    shared_alpha.fetch_max(value, Ordering::Relaxed);
+                    RootResult::Done { alpha, value }
+                }
+                None => RootResult::TimedOut,
             };
-            board.do_move(selected_move, color);
-            let ret = reverse_value(perfect_read_dfs(
-                &board,
-                color.opposite(),
-                Some(selected_move),
-                start_time,
-                assigned_time_ms,
-            ));
-            if let Some(ret_raw) = ret {
-                if ret_raw > max_eval {
-                    max_eval = ret_raw;
+            (Move::Mv(square), result, solver.stats())
+        })
+        .collect();
+
+    let mut best = None;
+    let mut timed_out = false;
+    let mut stats = SearchStats::default();
+    for (m, result, move_stats) in results {
+        stats.merge(&move_stats);
+        match result {
+            // 他の手の結果で狭めた alpha 以下の値は上界でしかないので、最善手の候補にしない
+            RootResult::Done {
+                alpha: searched_alpha,
+                value,
+            } if value > searched_alpha || searched_alpha == alpha => {
+                if best.is_none_or(|(_, best_value)| value > best_value) {
+                    best = Some((m, value));
                 }
+            }
+            RootResult::Done { .. } | RootResult::Skipped => {}
+            RootResult::TimedOut => timed_out = true,
+        }
+    }
+    RootSearch {
+        complete: !timed_out || best.is_some_and(|(_, value)| value >= beta),
+        best,
+        stats,
+    }
+}
+
+/// 何も読み終わらなかったときに打つ手
+fn fallback_move(board: &Board, color: Color) -> Move {
+    let can_put = board.can_put(color);
+    match move_order::ordered_moves(
+        &mut board.clone(),
+        color,
+        can_put,
+        None,
+        MoveOrdering::default(),
+        &Mobility,
+    )
+    .first()
+    {
+        Some(&(square, _)) => Move::Mv(square),
+        None => Move::Pass,
+    }
+}
+
+/// 勝ち・引き分け・負けを読みます。
+fn solve_wld(board: &Board, color: Color, start_time: Instant, assigned_time_ms: i32) -> Solution {
+    let root = search_root(
+        board,
+        color,
+        Window::Wld.bounds(),
+        None,
+        start_time,
+        assigned_time_ms,
+    );
+    let stats = SearchStats {
+        depth: (64 - board.sum_stones()) as u32,
+        elapsed: start_time.elapsed(),
+        ..root.stats
+    };
+    match root.best {
+        Some((best_move, value)) => Solution {
+            best_move,
+            value: value.signum(),
+            proof: if root.complete {
+                Proof::Wld
             } else {
-                return None;
+                Proof::Unproven
+            },
+            pv: vec![best_move],
+            stats,
+        },
+        None => {
+            let best_move = fallback_move(board, color);
+            Solution {
+                best_move,
+                value: -1,
+                proof: Proof::Unproven,
+                pv: vec![best_move],
+                stats,
             }
         }
-        Some(max_eval)
     }
 }
 
-pub fn decide(board: &Board, color: Color, assigned_time_ms: i32) -> (Move, Option<i32>) {
-    let moves = board.valid_moves(color);
-    let moves_len = moves.len();
-    let mut max_eval = -64;
-    let mut best_move = Move::Pass;
-    let start_time = std::time::Instant::now();
-    let stone_num = board.sum_stones();
+/// `value` が `color` の手番から見た正確な石差のとき、その石差で終局する手順を求めます。
+///
+/// 各局面で、値が `value` 以上になる手を null window で探して進めます。`first` があれば最初に
+/// 確かめます。時間切れの場合はそこまでの手順を返します。
+fn principal_variation(
+    board: &Board,
+    color: Color,
+    value: i32,
+    first: Move,
+    start_time: Instant,
+    assigned_time_ms: i32,
+    stats: &mut SearchStats,
+) -> Vec<Move> {
+    let tt = TranspositionTable::shared();
+    let mut solver = Solver::new(tt, start_time, assigned_time_ms);
+    let mut board = board.clone();
+    let mut color = color;
+    let mut value = value;
+    let mut first = Some(first);
+    let mut pv = vec![];
+    loop {
+        let can_put = board.can_put(color);
+        if can_put == 0 {
+            if board.can_put(color.opposite()) == 0 {
+                break;
+            }
+            first = None;
+            pv.push(Move::Pass);
+            color = color.opposite();
+            value = -value;
+            continue;
+        }
+        let tt_move = match first.take() {
+            Some(Move::Mv(square)) => Some(square),
+            _ => tt
+                .probe(zobrist::hash_with_color(&board, color))
+                .and_then(|entry| entry.best_move),
+        };
+        let moves = move_order::ordered_moves(
+            &mut board,
+            color,
+            can_put,
+            tt_move,
+            MoveOrdering::default(),
+            &Mobility,
+        );
+        let mut found = None;
+        for (square, flips) in moves {
+            board.make(square, flips, color);
+            // 相手から見て -value 以下なら、この手で value 以上になる
+            match solver.solve(&mut board, color.opposite(), -value, -value + 1, false) {
+                Some(child) if child <= -value => {
+                    found = Some(square);
+                    break;
+                }
+                Some(_) => board.unmake(square, flips, color),
+                None => {
+                    board.unmake(square, flips, color);
+                    break;
+                }
+            }
+        }
+        // 時間切れ (value が正確な石差なら、手は必ず見つかる)
+        let Some(square) = found else {
+            break;
+        };
+        pv.push(Move::Mv(square));
+        color = color.opposite();
+        value = -value;
+    }
+    stats.merge(&solver.stats());
+    pv
+}
 
-    let results: Vec<_> = moves
-        .into_par_iter()
-        .filter_map(|m| {
-            let mut board = board.clone();
-            let selected_move = Move::Mv {
-                x_ah: m.0 as u32,
-                y_18: m.1 as u32,
-            };
-            board.do_move(selected_move, color);
-            let ret = reverse_value(calc_by_dfs(
-                &board,
-                color.opposite(),
-                Some(selected_move),
-                &start_time,
+/// 勝ち (1)・引き分け (0)・負け (-1) を読み切り、最善手と結果を返します。
+///
+/// 時間切れの場合も、読み終わった手の中での最善手を `Proof::Unproven` として返します。
+pub fn decide(board: &Board, color: Color, assigned_time_ms: i32) -> Solution {
+    let start_time = Instant::now();
+    TranspositionTable::shared().new_search();
+    solve_wld(board, color, start_time, assigned_time_ms)
+}
+
+/// 最終的な石差を読み切り、最善手と石差を返します。
+///
+/// まず勝敗を読み切り、その結果で窓を狭めて石差を読みます。石差を読み切る前に時間切れになった
+/// 場合は、勝敗までの結果 (`Proof::Wld`) かそれ以下の結果を返します。
+pub fn perfect_read(board: &Board, color: Color, assigned_time_ms: i32) -> Solution {
+    let start_time = Instant::now();
+    TranspositionTable::shared().new_search();
+    let wld = solve_wld(board, color, start_time, assigned_time_ms);
+    if wld.proof != Proof::Wld {
+        return wld;
+    }
+    let root = match wld.value {
+        // 引き分けなら石差は 0 で確定している
+        0 => RootSearch {
+            best: Some((wld.best_move, 0)),
+            complete: true,
+            stats: SearchStats::default(),
+        },
+        value => search_root(
+            board,
+            color,
+            if value > 0 { (0, 64) } else { (-64, 0) },
+            Some(wld.best_move),
+            start_time,
+            assigned_time_ms,
+        ),
+    };
+    let mut stats = wld.stats;
+    stats.merge(&root.stats);
+    let mut solution = match root.best {
+        Some((best_move, value)) if root.complete => {
+            let pv = principal_variation(
+                board,
+                color,
+                value,
+                best_move,
+                start_time,
                 assigned_time_ms,
-                stone_num + 1,
-            ));
-            ret.map(|ret| (selected_move, ret))
-        })
-        .collect();
+                &mut stats,
+            );
+            Solution {
+                best_move,
+                value,
+                proof: Proof::Exact,
+                pv,
+                stats,
+            }
+        }
+        // 読み終わった手が勝敗の読みと同じ結果なら、石差の多い方を選ぶ
+        Some((best_move, value)) if value.signum() == wld.value => Solution {
+            best_move,
+            pv: vec![best_move],
+            ..wld
+        },
+        _ => wld,
+    };
+    solution.stats = SearchStats {
+        elapsed: start_time.elapsed(),
+        ..stats
+    };
+    solution
+}
 
-    let results_len = results.len();
+/// 解析でのルートの手の石差
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MoveScore {
+    pub mv: Move,
+    /// 手番側から見た石差の下界と上界 (読み切れば等しくなります)
+    pub lower: i32,
+    pub upper: i32,
+}
 
-    for (selected_move, ret) in results {
-        if ret > max_eval {
-            max_eval = ret;
-            best_move = selected_move;
-        }
-        if ret == 1 {
-            return (best_move, Some(max_eval));
-        }
+impl MoveScore {
+    /// 読み切った石差
+    pub fn exact(&self) -> Option<i32> {
+        (self.lower == self.upper).then_some(self.lower)
     }
 
-    if max_eval == 0 {
-        return (best_move, Some(max_eval));
+    pub fn proof(&self) -> Proof {
+        if self.lower == self.upper {
+            Proof::Exact
+        } else if self.lower > 0 || self.upper < 0 {
+            Proof::Wld
+        } else {
+            Proof::Unproven
+        }
     }
-    if results_len < moves_len {
-        return (Move::GiveUp, None);
+
+    /// `color` が打った後の局面 `board` を窓 `(alpha, beta)` で読み、下界・上界を狭めます。
+    /// 時間切れなら `false` を返します。
+    fn refine(
+        &mut self,
+        solver: &mut Solver,
+        board: &mut Board,
+        color: Color,
+        (alpha, beta): (i32, i32),
+        passed: bool,
+    ) -> bool {
+        let Some(value) = solver.solve(board, color.opposite(), -beta, -alpha, passed) else {
+            return false;
+        };
+        let value = -value;
+        if value <= alpha {
+            self.upper = self.upper.min(value);
+        } else if value >= beta {
+            self.lower = self.lower.max(value);
+        } else {
+            self.lower = value;
+            self.upper = value;
+        }
+        true
     }
-    (best_move, Some(max_eval))
-
-    // let result = moves.into_par_iter().filter_map(|m| {
-    //     let mut board = board.clone();
-    //     let selected_move = Move::Mv {
-    //         x_ah: m.0 as u32,
-    //         y_18: m.1 as u32,
-    //     };
-    //     board.do_move(selected_move, color);
-    //     let ret = reverse_value(calc_by_dfs(
-    //         &board,
-    //         color.opposite(),
-    //         Some(selected_move),
-    //         &start_time,
-    //         assigned_time_ms,
-    //     ));
-    //     if ret == None {
-    //         return None;
-    //     }
-    //     let ret = ret.unwrap();
-    //     if ret > max_eval {
-    //         max_eval = ret;
-    //         best_move = selected_move;
-    //     }
-    //     if ret == 1 {
-    //         return Some((best_move, Some(max_eval)));
-    //     }
-    //     None
-    // });
-
-    // for m in moves {
-    //     let mut board = board.clone();
-    //     let selected_move = Move::Mv {
-    //         x_ah: m.0 as u32,
-    //         y_18: m.1 as u32,
-    //     };
-    //     board.do_move(selected_move, color);
-    //     let ret = reverse_value(calc_by_dfs(
-    //         &board,
-    //         color.opposite(),
-    //         Some(selected_move),
-    //         &start_time,
-    //         assigned_time_ms,
-    //     ));
-    //     if ret == None {
-    //         return (Move::GiveUp, None);
-    //     }
-    //     let ret = ret.unwrap();
-    //     if ret > max_eval {
-    //         max_eval = ret;
-    //         best_move = selected_move;
-    //     }
-    //     if ret == 1 {
-    //         break;
-    //     }
-    // }
 }
 
-pub fn perfect_read(board: &Board, color: Color, assigned_time_ms: i32) -> (Move, Option<i32>) {
-    let moves = board.valid_moves(color);
-    let mut max_eval = -65;
-    let mut best_move = Move::Pass;
-    let start_time = std::time::Instant::now();
-
-    let moves_len = moves.len();
+/// 解析の結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Analysis {
+    /// 全ての合法手 (打てなければパス) の石差。下界の大きい順に並びます。
+    pub moves: Vec<MoveScore>,
+    pub stats: SearchStats,
+}
 
-    let results: Vec<(Move, i32)> = moves
+/// 全ての合法手について、打った後の最終的な石差を読み切ります (multi-PV)。
+///
+/// 手ごとにまず勝敗を、次に石差を読みます。時間切れになった手は、そこまでに分かった
+/// 下界・上界を返します。
+pub fn analyze(board: &Board, color: Color, assigned_time_ms: i32) -> Analysis {
+    let start_time = Instant::now();
+    let tt = TranspositionTable::shared();
+    tt.new_search();
+    let can_put = board.can_put(color);
+    let moves: Vec<(Move, u64)> = if can_put == 0 {
+        vec![(Move::Pass, 0)]
+    } else {
+        move_order::ordered_moves(
+            &mut board.clone(),
+            color,
+            can_put,
+            None,
+            MoveOrdering::default(),
+            &Mobility,
+        )
+        .into_iter()
+        .map(|(square, flips)| (Move::Mv(square), flips))
+        .collect()
+    };
+    let results: Vec<(MoveScore, SearchStats)> = moves
         .into_par_iter()
-        .filter_map(|m| {
+        .map(|(mv, flips)| {
             let mut board = board.clone();
-            let selected_move = Move::Mv {
-                x_ah: m.0 as u32,
-                y_18: m.1 as u32,
+            if let Move::Mv(square) = mv {
+                board.make(square, flips, color);
+            }
+            let passed = mv == Move::Pass;
+            let mut score = MoveScore {
+                mv,
+                lower: -64,
+                upper: 64,
             };
-            board.do_move(selected_move, color);
-            let ret = reverse_value(perfect_read_dfs(
-                &board,
-                color.opposite(),
-                Some(selected_move),
-                &start_time,
-                assigned_time_ms,
-            ));
-            ret.map(|ret| (selected_move, ret))
+            let mut solver = Solver::new(tt, start_time, assigned_time_ms);
+            if score.refine(&mut solver, &mut board, color, Window::Wld.bounds(), passed)
+                && score.exact().is_none()
+            {
+                let window = (score.lower - 1, score.upper + 1);
+                score.refine(&mut solver, &mut board, color, window, passed);
+            }
+            (score, solver.stats())
         })
         .collect();
 
-    let results_len = results.len();
-    if results_len < moves_len {
-        return (Move::GiveUp, None);
+    let mut stats = SearchStats::default();
+    let mut moves = Vec::with_capacity(results.len());
+    for (score, move_stats) in results {
+        stats.merge(&move_stats);
+        moves.push(score);
+    }
+    moves.sort_by_key(|score| std::cmp::Reverse((score.lower, score.upper)));
+    stats.depth = (64 - board.sum_stones()) as u32;
+    stats.elapsed = start_time.elapsed();
+    Analysis { moves, stats }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_othello::random_position;
+    use crate::eval;
+
+    /// 枝刈りなしの minimax
+    fn minimax(board: &mut Board, color: Color, passed: bool) -> i32 {
+        let can_put = board.can_put(color);
+        if can_put == 0 {
+            if passed {
+                return board.diff_stones(color);
+            }
+            return -minimax(board, color.opposite(), true);
+        }
+        let mut best = SCORE_MIN;
+        for square in Squares(can_put) {
+            let flips = board.flips(square, color);
+            board.make(square, flips, color);
+            best = best.max(-minimax(board, color.opposite(), false));
+            board.unmake(square, flips, color);
+        }
+        best
+    }
+
+    #[test]
+    fn test_alpha_beta_matches_minimax() {
+        // 小さい置換表で、置き換えと窓をまたいだ再利用も確かめる
+        let tt = TranspositionTable::new(64);
+        for _ in 0..10 {
+            let (mut board, color) = random_position(9);
+            let expected = minimax(&mut board.clone(), color, false);
+            let mut solver = Solver::new(&tt, Instant::now(), 10000);
+            let (alpha, beta) = Window::Exact.bounds();
+            assert_eq!(
+                solver.solve(&mut board, color, alpha, beta, false),
+                Some(expected)
+            );
+
+            // 窓の外の値は正しい向きの上界・下界になる
+            for n in [-40, -10, -1, 0, 1, 2, 10, 40] {
+                let (alpha, beta) = Window::AtLeast(n).bounds();
+                let value = solver.solve(&mut board, color, alpha, beta, false).unwrap();
+                assert_eq!(value >= n, expected >= n, "n = {n}");
+                if value <= alpha {
+                    assert!(expected <= value);
+                } else if value >= beta {
+                    assert!(expected >= value);
+                } else {
+                    assert_eq!(value, expected);
+                }
+            }
+        }
+        let stats = tt.stats();
+        assert!(stats.hits > 0 && stats.hits <= stats.probes);
     }
-    for (selected_move, ret) in results {
-        if ret > max_eval {
-            max_eval = ret;
-            best_move = selected_move;
+
+    #[test]
+    fn test_orderings_agree() {
+        for _ in 0..5 {
+            let (board, color) = random_position(9);
+            let expected = minimax(&mut board.clone(), color, false);
+            let weights = eval::Weights::fallback();
+            let evaluators: [(&str, &dyn Evaluator); 3] = [
+                ("mobility", &Mobility),
+                ("disc count", &eval::DiscCount),
+                ("pattern", &weights),
+            ];
+            for ordering in [MoveOrdering::None, MoveOrdering::FastestFirst] {
+                let tt = TranspositionTable::new(1024);
+                let mut solver = Solver::new(&tt, Instant::now(), 10000).with_ordering(ordering);
+                let (alpha, beta) = Window::Exact.bounds();
+                let value = solver.solve(&mut board.clone(), color, alpha, beta, false);
+                assert_eq!(value, Some(expected), "{ordering:?}");
+            }
+            for (name, evaluator) in evaluators {
+                let tt = TranspositionTable::new(1024);
+                let mut solver = Solver::new(&tt, Instant::now(), 10000)
+                    .with_ordering(MoveOrdering::Evaluation)
+                    .with_evaluator(evaluator);
+                let (alpha, beta) = Window::Exact.bounds();
+                let value = solver.solve(&mut board.clone(), color, alpha, beta, false);
+                assert_eq!(value, Some(expected), "{name}");
+            }
         }
     }
 
-    // for m in moves {
-    //     let mut board = board.clone();
-    //     let selected_move = Move::Mv {
-    //         x_ah: m.0 as u32,
-    //         y_18: m.1 as u32,
-    //     };
-    //     board.do_move(selected_move, color);
-    //     let ret = reverse_value(perfect_read_dfs(
-    //         &board,
-    //         color.opposite(),
-    //         Some(selected_move),
-    //         &start_time,
-    //         assigned_time_ms,
-    //     ));
-    //     if let Some(ret_raw) = ret {
-    //         if ret_raw > max_eval {
-    //             max_eval = ret_raw;
-    //             best_move = selected_move;
-    //         }
-    //     } else {
-    //         return (Move::GiveUp, None);
-    //     }
-    // }
-    (best_move, Some(max_eval))
+    #[test]
+    fn test_root_windows() {
+        for _ in 0..10 {
+            let (board, color) = random_position(9);
+            let expected = minimax(&mut board.clone(), color, false);
+            let solution = perfect_read(&board, color, 10000);
+            assert_eq!(solution.proof, Proof::Exact);
+            assert_eq!(solution.value, expected);
+            let mv = solution.best_move;
+            let mut after = board.clone();
+            after.do_move(mv, color);
+            assert_eq!(
+                -minimax(&mut after, color.opposite(), mv == Move::Pass),
+                expected
+            );
+
+            // 最善手順を打つと予想した石差で終局する
+            assert_eq!(solution.pv[0], mv);
+            let mut replay = board.clone();
+            let mut c = color;
+            for &m in &solution.pv {
+                assert!(replay.check_valid_move(m, c), "{m} in {:?}", solution.pv);
+                replay.do_move(m, c);
+                c = c.opposite();
+            }
+            assert!(replay.valid_moves(Color::Black).is_empty());
+            assert!(replay.valid_moves(Color::White).is_empty());
+            assert_eq!(replay.diff_stones(color), expected);
+
+            let wld = decide(&board, color, 10000);
+            assert_eq!(wld.proof, Proof::Wld);
+            assert_eq!(wld.value, expected.signum());
+        }
+    }
+
+    #[test]
+    fn test_analyze_scores_every_move() {
+        for _ in 0..5 {
+            let (board, color) = random_position(9);
+            let analysis = analyze(&board, color, 10000);
+            assert_eq!(analysis.moves.len(), board.valid_moves(color).len().max(1));
+            for score in &analysis.moves {
+                assert_eq!(score.proof(), Proof::Exact);
+                let mut after = board.clone();
+                after.do_move(score.mv, color);
+                let expected = -minimax(&mut after, color.opposite(), score.mv == Move::Pass);
+                assert_eq!(score.exact(), Some(expected), "{}", score.mv);
+            }
+            let best = perfect_read(&board, color, 10000);
+            assert_eq!(analysis.moves[0].exact(), Some(best.value));
+        }
+    }
+
+    #[test]
+    fn test_last_moves_match_minimax() {
+        for empties in 1..=last_moves::MAX_EMPTIES {
+            for _ in 0..50 {
+                let (board, color) = random_position(empties);
+                let expected = minimax(&mut board.clone(), color, false);
+                let mut nodes = 0;
+                let exact =
+                    last_moves::solve(&mut board.clone(), color, -64, 64, false, &mut nodes);
+                assert_eq!(exact, expected, "{board:?}");
+                for n in [-2, 0, 1, 3] {
+                    let (alpha, beta) = Window::AtLeast(n).bounds();
+                    let value = last_moves::solve(
+                        &mut board.clone(),
+                        color,
+                        alpha,
+                        beta,
+                        false,
+                        &mut nodes,
+                    );
+                    assert_eq!(value >= n, expected >= n);
+                }
+            }
+        }
+    }
 }
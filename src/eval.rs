@@ -0,0 +1,597 @@
+//! パターンによる静的評価関数です。
+//!
+//! 盤面から次のパターン (マスの並び) を取り出し、並びごとの重みを足し合わせます。
+//!
+//! - 辺と X 打ちのマス (10マス)
+//! - 隅の 3x3 (9マス) と 2x5 (10マス)
+//! - 長さ4から8の斜めの列
+//!
+//! それに着手可能数の差を重み付きで加えます。重みは序盤・中盤・終盤で変わるので、
+//! 石の数で局面を [`NUM_PHASES`] 段階に分けて段階ごとに持ちます。
+//! 同じパターンを8通りに対称変換した並びは同じ重みの表を使うので、
+//! 評価値は盤面の回転・鏡映で変わりません。
+//!
+//! 重みは起動時にファイル ([`Weights::load`]) から読み、読めなければ
+//! 位置の価値と石数から作った組み込みの重み ([`Weights::fallback`]) を使います。
+//!
+//! 探索に渡す評価関数は [`Evaluator`] で、パターンの他に着手可能数や石数でも評価できます。
+
+use crate::bit_othello::{bit_count, Board, Pos};
+use crate::proto::Color;
+use crate::square::Square;
+use crate::symmetry::Symmetry;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::OnceLock;
+use thiserror::Error;
+
+/// 評価値の単位 (石1個の差がこの値になります)
+pub const SCALE: i32 = 128;
+/// 石の数で分けた局面の段階の数
+pub const NUM_PHASES: usize = 12;
+/// 重みファイルの先頭
+const MAGIC: [u8; 4] = *b"FLRW";
+/// 重みファイルの形式の版 (パターンや段階を変えたら上げます)
+pub const VERSION: u32 = 3;
+
+/// パターンの名前と、対称変換する前のマスの並び (1始まりの座標)
+const PATTERNS: [(&str, &[Pos]); 8] = [
+    (
+        "edge+2x",
+        &[
+            (1, 1),
+            (2, 1),
+            (3, 1),
+            (4, 1),
+            (5, 1),
+            (6, 1),
+            (7, 1),
+            (8, 1),
+            (2, 2),
+            (7, 2),
+        ],
+    ),
+    (
+        "corner3x3",
+        &[
+            (1, 1),
+            (2, 1),
+            (3, 1),
+            (1, 2),
+            (2, 2),
+            (3, 2),
+            (1, 3),
+            (2, 3),
+            (3, 3),
+        ],
+    ),
+    (
+        "corner2x5",
+        &[
+            (1, 1),
+            (2, 1),
+            (3, 1),
+            (4, 1),
+            (5, 1),
+            (1, 2),
+            (2, 2),
+            (3, 2),
+            (4, 2),
+            (5, 2),
+        ],
+    ),
+    (
+        "diag8",
+        &[
+            (1, 1),
+            (2, 2),
+            (3, 3),
+            (4, 4),
+            (5, 5),
+            (6, 6),
+            (7, 7),
+            (8, 8),
+        ],
+    ),
+    (
+        "diag7",
+        &[(1, 2), (2, 3), (3, 4), (4, 5), (5, 6), (6, 7), (7, 8)],
+    ),
+    ("diag6", &[(1, 3), (2, 4), (3, 5), (4, 6), (5, 7), (6, 8)]),
+    ("diag5", &[(1, 4), (2, 5), (3, 6), (4, 7), (5, 8)]),
+    ("diag4", &[(1, 5), (2, 6), (3, 7), (4, 8)]),
+];
+
+/// 盤上の1つのパターンの並び (対称変換したもの)
+#[derive(Debug)]
+pub struct Feature {
+    /// [`PATTERNS`] での番号
+    pub pattern: usize,
+    squares: Vec<Square>,
+    /// 同じマスを別の順に読む並び (辺を逆から読むなど、対称変換で同じマスに移るもの)
+    others: Vec<Vec<Square>>,
+}
+
+impl Feature {
+    /// 並びの番号 (どの順に読むかで変わらないよう、読み方ごとの番号の最小値にします)
+    fn index(&self, player: u64, opponent: u64) -> usize {
+        let index_of = |squares: &[Square]| {
+            squares.iter().fold(0, |index, square| {
+                let bit = square.bit();
+                let cell = if player & bit != 0 {
+                    1
+                } else if opponent & bit != 0 {
+                    2
+                } else {
+                    0
+                };
+                index * 3 + cell
+            })
+        };
+        self.others
+            .iter()
+            .map(|squares| index_of(squares))
+            .fold(index_of(&self.squares), usize::min)
+    }
+}
+
+/// 全てのパターンを8通りに対称変換した並び
+///
+/// 同じマスの組になる並びは、読む順が違っても1つにまとめます (同じ局面を2回数えないため)。
+pub fn features() -> &'static [Feature] {
+    static FEATURES: OnceLock<Vec<Feature>> = OnceLock::new();
+    FEATURES.get_or_init(|| {
+        let mut features: Vec<Feature> = Vec::new();
+        for (pattern, (_, squares)) in PATTERNS.iter().enumerate() {
+            for sym in Symmetry::ALL {
+                let squares: Vec<Square> = squares
+                    .iter()
+                    .map(|&pos| Square::from_pos(sym.apply_pos(pos)).unwrap())
+                    .collect();
+                let same_squares =
+                    |f: &&mut Feature| squares.iter().all(|square| f.squares.contains(square));
+                match features.iter_mut().find(same_squares) {
+                    Some(feature) => {
+                        if feature.squares != squares && !feature.others.contains(&squares) {
+                            feature.others.push(squares);
+                        }
+                    }
+                    None => features.push(Feature {
+                        pattern,
+                        squares,
+                        others: vec![],
+                    }),
+                }
+            }
+        }
+        features
+    })
+}
+
+/// パターンの名前
+pub fn pattern_name(pattern: usize) -> &'static str {
+    PATTERNS[pattern].0
+}
+
+/// パターンの並びの数 (重みの表の大きさ) `3^マス数`
+pub fn pattern_size(pattern: usize) -> usize {
+    3usize.pow(PATTERNS[pattern].1.len() as u32)
+}
+
+/// 1つの段階の重みの数 (全パターンの表と着手可能数の重み1つ)
+fn phase_len() -> usize {
+    (0..PATTERNS.len()).map(pattern_size).sum::<usize>() + 1
+}
+
+/// 各パターンの表が段階の重みの中で始まる位置
+fn pattern_offsets() -> [usize; PATTERNS.len()] {
+    let mut offsets = [0; PATTERNS.len()];
+    for pattern in 1..PATTERNS.len() {
+        offsets[pattern] = offsets[pattern - 1] + pattern_size(pattern - 1);
+    }
+    offsets
+}
+
+/// 盤面の段階 (0 が初期局面、`NUM_PHASES - 1` が終局間際)
+///
+/// 5手ごとに1段階で、石が64個の局面は評価しないので最後の段階に含めます。
+pub fn phase(board: &Board) -> usize {
+    let played = board.sum_stones().saturating_sub(4) as usize;
+    (played / 5).min(NUM_PHASES - 1)
+}
+
+/// 評価に使う盤面の特徴
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Features {
+    pub phase: usize,
+    /// 各並びの重みの位置 (段階の重みの中での位置、[`features`] と同じ順)
+    pub indexes: Vec<usize>,
+    /// 手番側と相手の着手可能数の差
+    pub mobility: i32,
+}
+
+/// `color` の手番から見た盤面の特徴を取り出します。
+///
+/// 並びのマスは空き・手番側・相手をそれぞれ 0・1・2 とした3進数で番号を付けます。
+pub fn extract(board: &Board, color: Color) -> Features {
+    let (player, opponent) = board.player_opponent(color);
+    let offsets = pattern_offsets();
+    let indexes = features()
+        .iter()
+        .map(|feature| offsets[feature.pattern] + feature.index(player, opponent))
+        .collect();
+    let mobility =
+        bit_count(board.can_put(color)) as i32 - bit_count(board.can_put(color.opposite())) as i32;
+    Features {
+        phase: phase(board),
+        indexes,
+        mobility,
+    }
+}
+
+/// 重みファイルを読めなかった理由
+#[derive(Debug, Error)]
+pub enum LoadWeightsError {
+    #[error("couldn't read weight file: {0}")]
+    Io(#[from] io::Error),
+    #[error("not a weight file")]
+    BadMagic,
+    #[error("unsupported weight file version {0} (expected {VERSION})")]
+    UnsupportedVersion(u32),
+    #[error("weight file patterns don't match this engine")]
+    LayoutMismatch,
+}
+
+/// 評価関数の重み
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Weights {
+    /// 段階ごとに、全パターンの表を並べた後に着手可能数の重みを置きます。
+    values: Vec<i16>,
+}
+
+impl Weights {
+    /// 全ての重みが 0 の重み
+    pub fn zero() -> Self {
+        Self {
+            values: vec![0; NUM_PHASES * phase_len()],
+        }
+    }
+
+    /// 組み込みの重みを作ります。
+    ///
+    /// 序盤は隅を高く X 打ち・C 打ちを低くしたマスの価値を、終盤ほど石数を重く見ます。
+    /// 着手可能数は序盤ほど重く見ます。
+    pub fn fallback() -> Self {
+        // マスの価値 (石数の単位、左上の4x4、残りは対称)
+        const POSITIONAL: [[f64; 4]; 4] = [
+            [8.0, -3.0, 1.0, 0.5],
+            [-3.0, -6.0, -0.5, -0.5],
+            [1.0, -0.5, 0.5, 0.0],
+            [0.5, -0.5, 0.0, 0.0],
+        ];
+        let value_of = |square: Square| {
+            let fold = |c: u32| (c.min(9 - c) - 1) as usize;
+            POSITIONAL[fold(square.y())][fold(square.x())]
+        };
+        // 各マスがいくつの並びに含まれるか (重複して数えないように割ります)
+        let mut coverage = [0u32; 64];
+        for feature in features() {
+            for square in &feature.squares {
+                coverage[square.index()] += 1;
+            }
+        }
+        let offsets = pattern_offsets();
+        let mut weights = Self::zero();
+        for phase in 0..NUM_PHASES {
+            let t = phase as f64 / (NUM_PHASES - 1) as f64;
+            let base = phase * phase_len();
+            for (pattern, (_, squares)) in PATTERNS.iter().enumerate() {
+                // 対称変換しても価値は変わらないので、元の並びで計算すればよい
+                let values: Vec<f64> = squares
+                    .iter()
+                    .map(|&pos| {
+                        let square = Square::from_pos(pos).unwrap();
+                        ((1.0 - t) * value_of(square) + t) / coverage[square.index()] as f64
+                    })
+                    .collect();
+                for index in 0..pattern_size(pattern) {
+                    let mut rest = index;
+                    let mut score = 0.0;
+                    for value in values.iter().rev() {
+                        match rest % 3 {
+                            1 => score += value,
+                            2 => score -= value,
+                            _ => {}
+                        }
+                        rest /= 3;
+                    }
+                    weights.values[base + offsets[pattern] + index] =
+                        (score * SCALE as f64).round() as i16;
+                }
+            }
+            weights.values[base + phase_len() - 1] =
+                ((1.0 - t) * SCALE as f64 / 2.0).round() as i16;
+        }
+        weights
+    }
+
+    /// 段階 `phase` の重み (全パターンの表と着手可能数の重み)
+    pub fn phase(&self, phase: usize) -> &[i16] {
+        let len = phase_len();
+        &self.values[phase * len..(phase + 1) * len]
+    }
+
+    /// 段階 `phase` の重み (学習で書き換える用)
+    pub fn phase_mut(&mut self, phase: usize) -> &mut [i16] {
+        let len = phase_len();
+        &mut self.values[phase * len..(phase + 1) * len]
+    }
+
+    /// 特徴から評価値を計算します。
+    pub fn score(&self, features: &Features) -> i32 {
+        let weights = self.phase(features.phase);
+        let patterns: i32 = features
+            .indexes
+            .iter()
+            .map(|&index| weights[index] as i32)
+            .sum();
+        let mobility = weights[weights.len() - 1] as i32 * features.mobility;
+        (patterns + mobility).clamp(-64 * SCALE, 64 * SCALE)
+    }
+
+    /// `color` の手番から見た評価値 (石差 × [`SCALE`] の見込み) を返します。
+    pub fn evaluate(&self, board: &Board, color: Color) -> i32 {
+        self.score(&extract(board, color))
+    }
+
+    /// 重みを読み込みます。
+    ///
+    /// 形式は先頭から `FLRW`・版・段階の数・パターンの数・各パターンのマス数 (いずれも u32) と、
+    /// 段階ごとの重み (i16) で、全てリトルエンディアンです。
+    pub fn read(reader: &mut impl Read) -> Result<Self, LoadWeightsError> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(LoadWeightsError::BadMagic);
+        }
+        let mut read_u32 = || -> io::Result<u32> {
+            let mut buf = [0; 4];
+            reader.read_exact(&mut buf)?;
+            Ok(u32::from_le_bytes(buf))
+        };
+        let version = read_u32()?;
+        if version != VERSION {
+            return Err(LoadWeightsError::UnsupportedVersion(version));
+        }
+        if read_u32()? as usize != NUM_PHASES || read_u32()? as usize != PATTERNS.len() {
+            return Err(LoadWeightsError::LayoutMismatch);
+        }
+        for (_, squares) in PATTERNS {
+            if read_u32()? as usize != squares.len() {
+                return Err(LoadWeightsError::LayoutMismatch);
+            }
+        }
+        let mut weights = Self::zero();
+        let mut buf = vec![0; weights.values.len() * 2];
+        reader.read_exact(&mut buf)?;
+        for (value, bytes) in weights.values.iter_mut().zip(buf.chunks_exact(2)) {
+            *value = i16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        Ok(weights)
+    }
+
+    /// [`Weights::read`] と同じ形式で書き出します。
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(NUM_PHASES as u32).to_le_bytes())?;
+        writer.write_all(&(PATTERNS.len() as u32).to_le_bytes())?;
+        for (_, squares) in PATTERNS {
+            writer.write_all(&(squares.len() as u32).to_le_bytes())?;
+        }
+        let bytes: Vec<u8> = self.values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        writer.write_all(&bytes)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LoadWeightsError> {
+        Self::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+}
+
+/// 盤面の評価関数です。
+///
+/// 探索 (モンテカルロ木探索のプレイアウトと UCB、読み切りの手の並べ方) に渡すと、
+/// 探索のコードを変えずに評価の仕方を入れ替えて比べられます。
+pub trait Evaluator: Sync {
+    /// `color` から見た評価値 (大きいほど `color` が有利)
+    fn evaluate(&self, board: &Board, color: Color) -> i32;
+
+    /// 評価値の1単位 (石1個、着手可能数1つ) の大きさ
+    fn unit(&self) -> i32 {
+        1
+    }
+}
+
+/// 着手可能数の差で評価します (終局していれば勝敗 × 64)。
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Mobility;
+
+impl Evaluator for Mobility {
+    fn evaluate(&self, board: &Board, color: Color) -> i32 {
+        board.canput_diff(color)
+    }
+}
+
+/// 石数の差で評価します。
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiscCount;
+
+impl Evaluator for DiscCount {
+    fn evaluate(&self, board: &Board, color: Color) -> i32 {
+        board.diff_stones(color)
+    }
+}
+
+/// パターンで評価します。
+impl Evaluator for Weights {
+    fn evaluate(&self, board: &Board, color: Color) -> i32 {
+        Weights::evaluate(self, board, color)
+    }
+
+    fn unit(&self) -> i32 {
+        SCALE
+    }
+}
+
+static WEIGHTS: OnceLock<Weights> = OnceLock::new();
+
+/// エンジン全体で使う重みを設定します。最初の1回だけ有効で、設定できたら `true` を返します。
+pub fn install(weights: Weights) -> bool {
+    WEIGHTS.set(weights).is_ok()
+}
+
+/// エンジン全体で使う重み (設定されていなければ組み込みの重み)
+pub fn weights() -> &'static Weights {
+    WEIGHTS.get_or_init(Weights::fallback)
+}
+
+/// [`weights`] で `color` の手番から見た評価値を返します。
+pub fn evaluate(board: &Board, color: Color) -> i32 {
+    weights().evaluate(board, color)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_othello::Position;
+    use crate::proto::Move;
+    use rand::Rng;
+
+    #[test]
+    fn test_features() {
+        let features = features();
+        // 同じマスの並びは1つにまとめる (辺・隅 3x3・斜めは2通りの読み方を1つに)
+        let count = |pattern: usize| features.iter().filter(|f| f.pattern == pattern).count();
+        assert_eq!(count(0), 4);
+        assert_eq!(count(1), 4);
+        assert_eq!(count(2), 8);
+        assert_eq!(count(3), 2);
+        assert_eq!(count(7), 4);
+        assert!(features
+            .iter()
+            .all(|f| f.others.len() == usize::from(f.pattern != 2)));
+        // 全てのマスがどれかの並びに入る
+        let covered = features
+            .iter()
+            .flat_map(|f| f.squares.iter())
+            .fold(0, |mask, square| mask | square.bit());
+        assert_eq!(covered, u64::MAX);
+    }
+
+    #[test]
+    fn test_symmetry_invariant() {
+        let fallback = Weights::fallback();
+        let mut rng = rand::thread_rng();
+        // 読む順を揃えているので、対称でない重みでも評価値は対称変換で変わらない
+        let mut random = Weights::zero();
+        for value in random.values.iter_mut() {
+            *value = rng.gen_range(-100..=100);
+        }
+        let mut board = Board::new();
+        let mut color = Color::Black;
+        for _ in 0..60 {
+            let moves = board.valid_moves(color);
+            if !moves.is_empty() {
+                board.do_move(Move::Mv(moves[rng.gen_range(0..moves.len())]), color);
+            }
+            color = color.opposite();
+            let value = fallback.evaluate(&board, color);
+            assert_eq!(value, -fallback.evaluate(&board, color.opposite()));
+            for weights in [&fallback, &random] {
+                let value = weights.evaluate(&board, color);
+                for sym in Symmetry::ALL {
+                    assert_eq!(weights.evaluate(&board.transform(sym), color), value);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_fallback_prefers_corners() {
+        assert_eq!(evaluate(&Board::new(), Color::Black), 0);
+        // 黒が隅を取っていて、白が X 打ちをしている
+        let position: Position = concat!(
+            "X-------",
+            "-O------",
+            "--------",
+            "---OX---",
+            "---XO---",
+            "--------",
+            "--------",
+            "-------- X"
+        )
+        .parse()
+        .unwrap();
+        assert!(Weights::fallback().evaluate(&position.board, Color::Black) > SCALE);
+    }
+
+    #[test]
+    fn test_evaluators() {
+        let mut board = Board::new();
+        board.do_move(Move::Mv("C4".parse().unwrap()), Color::Black);
+        let evaluators: [&dyn Evaluator; 3] = [&Mobility, &DiscCount, weights()];
+        for evaluator in evaluators {
+            // どの評価関数も手番を入れ替えると符号が変わる
+            assert_eq!(
+                evaluator.evaluate(&board, Color::Black),
+                -evaluator.evaluate(&board, Color::White)
+            );
+        }
+        assert_eq!(DiscCount.evaluate(&board, Color::Black), 3);
+        assert_eq!(
+            Mobility.evaluate(&board, Color::Black),
+            board.canput_diff(Color::Black)
+        );
+        assert_eq!(weights().unit(), SCALE);
+    }
+
+    #[test]
+    fn test_read_write() {
+        let mut weights = Weights::fallback();
+        weights.phase_mut(3)[7] = -1234;
+        let mut bytes = Vec::new();
+        weights.write(&mut bytes).unwrap();
+        assert_eq!(Weights::read(&mut bytes.as_slice()).unwrap(), weights);
+
+        let mut bad = bytes.clone();
+        bad[0] = b'X';
+        assert!(matches!(
+            Weights::read(&mut bad.as_slice()),
+            Err(LoadWeightsError::BadMagic)
+        ));
+        let mut bad = bytes.clone();
+        bad[4] = 99;
+        assert!(matches!(
+            Weights::read(&mut bad.as_slice()),
+            Err(LoadWeightsError::UnsupportedVersion(99))
+        ));
+        let mut bad = bytes.clone();
+        bad[12] = 1;
+        assert!(matches!(
+            Weights::read(&mut bad.as_slice()),
+            Err(LoadWeightsError::LayoutMismatch)
+        ));
+        // 途中で切れたファイル
+        assert!(matches!(
+            Weights::read(&mut &bytes[..bytes.len() - 1]),
+            Err(LoadWeightsError::Io(_))
+        ));
+    }
+}
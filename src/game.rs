@@ -0,0 +1,214 @@
+use crate::bit_othello::{Board, Flips, IllegalMove, Position};
+use crate::proto::{Color, Move};
+use crate::zobrist;
+
+/// 対局の状態 (盤面、手番、棋譜) を管理します。
+///
+/// パスも1手として棋譜に残すので、手番は常に交互になります。
+///
+/// ```
+/// use fl_reversi_rs::game::Game;
+/// use fl_reversi_rs::{Color, Move};
+///
+/// let mut game = Game::new();
+/// let c4 = Move::Mv("C4".parse().unwrap());
+/// game.play(c4).unwrap();
+/// assert_eq!(game.side_to_move(), Color::White);
+/// assert_eq!(game.undo(), Some(c4));
+/// assert_eq!(game.side_to_move(), Color::Black);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Game {
+    board: Board,
+    color: Color,
+    kihu: Vec<Move>,
+    flips: Vec<Flips>,
+    redo_list: Vec<Move>,
+    hash: u64,
+}
+
+impl Game {
+    pub fn new() -> Self {
+        Self::from_position(Position {
+            board: Board::new(),
+            color: Color::Black,
+        })
+    }
+
+    /// 途中の局面から対局を始めます。
+    pub fn from_position(position: Position) -> Self {
+        Self {
+            hash: zobrist::hash(&position.board),
+            board: position.board,
+            color: position.color,
+            kihu: vec![],
+            flips: vec![],
+            redo_list: vec![],
+        }
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn side_to_move(&self) -> Color {
+        self.color
+    }
+
+    /// 盤面と手番のZobristハッシュ (着手ごとに差分で更新されます)
+    pub fn hash(&self) -> u64 {
+        if self.color == Color::Black {
+            self.hash
+        } else {
+            self.hash ^ zobrist::WHITE_TO_MOVE
+        }
+    }
+
+    pub fn position(&self) -> Position {
+        Position {
+            board: self.board.clone(),
+            color: self.color,
+        }
+    }
+
+    /// これまでに打たれた手 (パスを含む)
+    pub fn kihu(&self) -> &[Move] {
+        &self.kihu
+    }
+
+    /// 手番の側が打てる手がなく、パスしなければならないかどうか
+    pub fn must_pass(&self) -> bool {
+        self.board.side_to_play(self.color) == Some(self.color.opposite())
+    }
+
+    /// 両者とも打てる手がなければ終局です。
+    pub fn is_over(&self) -> bool {
+        self.board.side_to_play(self.color).is_none()
+    }
+
+    /// 終局していれば黒から見た石の差を返します。
+    pub fn final_score(&self) -> Option<i32> {
+        if self.is_over() {
+            Some(self.board.diff_stones(Color::Black))
+        } else {
+            None
+        }
+    }
+
+    /// 手番の側が `m` を打ちます。打てない手なら何も変えずにエラーを返します。
+    pub fn play(&mut self, m: Move) -> Result<Flips, IllegalMove> {
+        if self.is_over() {
+            return Err(IllegalMove::GameOver);
+        }
+        let flips = self.board.try_move(m, self.color)?;
+        if let Move::Mv(square) = m {
+            self.hash = zobrist::update(self.hash, square, flips, self.color);
+        }
+        self.kihu.push(m);
+        self.flips.push(flips);
+        self.color = self.color.opposite();
+        self.redo_list.clear();
+        Ok(flips)
+    }
+
+    /// 打てる手がなければパスします。パスした場合は `true` を返します。
+    pub fn pass_if_needed(&mut self) -> bool {
+        self.must_pass() && self.play(Move::Pass).is_ok()
+    }
+
+    /// 最後の手を取り消し、その手を返します。
+    pub fn undo(&mut self) -> Option<Move> {
+        let m = self.kihu.pop()?;
+        let flips = self
+            .flips
+            .pop()
+            .expect("flips must be recorded for each move");
+        self.color = self.color.opposite();
+        if let Move::Mv(square) = m {
+            self.board.unmake(square, flips, self.color);
+            self.hash = zobrist::update(self.hash, square, flips, self.color);
+        }
+        self.redo_list.push(m);
+        Some(m)
+    }
+
+    /// `undo` で取り消した手を打ち直し、その手を返します。
+    pub fn redo(&mut self) -> Option<Move> {
+        let m = self.redo_list.pop()?;
+        let redo_list = std::mem::take(&mut self.redo_list);
+        self.play(m).expect("redo must replay a legal move");
+        self.redo_list = redo_list;
+        Some(m)
+    }
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mv(s: &str) -> Move {
+        Move::Mv(s.parse().unwrap())
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut game = Game::new();
+        let moves = [mv("C4"), mv("C3"), mv("D3")];
+        for m in moves {
+            game.play(m).unwrap();
+        }
+        let after = game.board().clone();
+        let after_hash = game.hash();
+        assert_eq!(game.kihu(), &moves);
+
+        assert_eq!(game.undo(), Some(mv("D3")));
+        assert_eq!(game.undo(), Some(mv("C3")));
+        assert_eq!(game.side_to_move(), Color::White);
+        assert_eq!(game.redo(), Some(mv("C3")));
+        assert_eq!(game.redo(), Some(mv("D3")));
+        assert_eq!(game.redo(), None);
+        assert_eq!(game.board(), &after);
+        assert_eq!(game.hash(), after_hash);
+
+        while game.undo().is_some() {}
+        assert_eq!(game.board(), &Board::new());
+        assert_eq!(game.hash(), Game::new().hash());
+
+        // 新しい手を打つと redo はできなくなる
+        game.play(mv("F5")).unwrap();
+        assert_eq!(game.redo(), None);
+    }
+
+    #[test]
+    fn test_illegal_move_keeps_state() {
+        let mut game = Game::new();
+        assert_eq!(game.play(mv("A1")), Err(IllegalMove::NoFlips(mv("A1"))));
+        assert_eq!(game.play(Move::Pass), Err(IllegalMove::PassWithMoves));
+        assert!(game.kihu().is_empty());
+        assert_eq!(game.side_to_move(), Color::Black);
+    }
+
+    #[test]
+    fn test_pass_and_game_over() {
+        // 白は打てないが黒は A1 に打てる
+        let position: Position = format!("-OXXXXXX{} O", "X".repeat(56)).parse().unwrap();
+        let mut game = Game::from_position(position);
+        assert!(game.must_pass());
+        assert!(!game.is_over());
+        assert_eq!(game.final_score(), None);
+        assert!(game.pass_if_needed());
+        assert_eq!(game.kihu(), &[Move::Pass]);
+        assert!(!game.pass_if_needed());
+
+        game.play(mv("A1")).unwrap();
+        assert!(game.is_over());
+        assert_eq!(game.final_score(), Some(64));
+        assert_eq!(game.play(Move::Pass), Err(IllegalMove::GameOver));
+    }
+}
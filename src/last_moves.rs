@@ -0,0 +1,119 @@
+//! 空きマスが少ない (4個以下) 局面専用の読み切りです。
+//!
+//! 一般の読み切り ([`Solver`](crate::depth_first_search::Solver)) は合法手のリストを作り、
+//! 置換表を引き、手を並べ替えますが、終局直前ではその手間の方が大きくなります。
+//! ここでは空きマスを直接たどって裏返る石を数え、手のリストを作りません。
+//! 最後の1マスは打たずに、裏返る石の数から石差を計算します。
+
+use crate::bit_othello::{bit_count, Board, Squares};
+use crate::depth_first_search::SCORE_MIN;
+use crate::move_order::odd_regions;
+use crate::proto::Color;
+use crate::square::Square;
+
+/// この空きマス数以下の局面をここで読みます。
+pub const MAX_EMPTIES: u64 = 4;
+
+/// 空きマスが `square` だけの局面を `color` の手番で読み切り、手番側から見た石差を返します。
+pub fn solve_last1(board: &Board, color: Color, square: Square) -> i32 {
+    let diff = board.diff_stones(color);
+    let flips = board.flips(square, color);
+    if flips != 0 {
+        return diff + 2 * bit_count(flips) as i32 + 1;
+    }
+    // パスして相手が打つ
+    let flips = board.flips(square, color.opposite());
+    if flips != 0 {
+        return diff - 2 * bit_count(flips) as i32 - 1;
+    }
+    diff
+}
+
+/// 空きマスが `MAX_EMPTIES` 個以下の局面を窓 `(alpha, beta)` で読み切ります (fail-soft)。
+///
+/// `passed` は直前の手がパスだったかどうかで、読んだノード数を `nodes` に足します。
+pub fn solve(
+    board: &mut Board,
+    color: Color,
+    alpha: i32,
+    beta: i32,
+    passed: bool,
+    nodes: &mut u64,
+) -> i32 {
+    *nodes += 1;
+    let empty = !(board.black | board.white);
+    debug_assert!(bit_count(empty) <= MAX_EMPTIES);
+    if empty == 0 {
+        return board.diff_stones(color);
+    }
+    if empty & (empty - 1) == 0 {
+        return solve_last1(
+            board,
+            color,
+            Square::from_index_unchecked(empty.trailing_zeros() as usize),
+        );
+    }
+
+    // 空きが奇数個の領域から打つ
+    let odd = odd_regions(empty);
+    let mut alpha = alpha;
+    let mut best = SCORE_MIN;
+    let mut moved = false;
+    for square in Squares(empty & odd).chain(Squares(empty & !odd)) {
+        let flips = board.flips(square, color);
+        if flips == 0 {
+            continue;
+        }
+        moved = true;
+        board.make(square, flips, color);
+        let value = -solve(board, color.opposite(), -beta, -alpha, false, nodes);
+        board.unmake(square, flips, color);
+        if value > best {
+            best = value;
+            if best > alpha {
+                alpha = best;
+                if alpha >= beta {
+                    break;
+                }
+            }
+        }
+    }
+    if !moved {
+        if passed {
+            return board.diff_stones(color);
+        }
+        return -solve(board, color.opposite(), -beta, -alpha, true, nodes);
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_othello::Position;
+
+    #[test]
+    fn test_solve_last1() {
+        // 黒が H8 に打つと G8 と G7 が裏返る
+        let position: Position = concat!(
+            "XXXXXXXX",
+            "XXXXXXXX",
+            "XXXXXXXX",
+            "XXXXXXXX",
+            "XXXXXXXX",
+            "XXXXXXOX",
+            "XXXXXXOX",
+            "XXXXXXO- X"
+        )
+        .parse()
+        .unwrap();
+        let board = position.board;
+        let h8 = "H8".parse().unwrap();
+        let mut after = board.clone();
+        after.do_move(crate::proto::Move::Mv(h8), Color::Black);
+        assert_eq!(after.diff_stones(Color::Black), 62);
+        assert_eq!(solve_last1(&board, Color::Black, h8), 62);
+        // 白番でも白は打てないので、パスして黒が打つ
+        assert_eq!(solve_last1(&board, Color::White, h8), -62);
+    }
+}
@@ -0,0 +1,40 @@
+//! オセロAIのエンジン部分です。
+//!
+//! ビットボードによる盤面の表現と合法手の生成、定石、探索をライブラリとして公開します。
+//! クライアント (`fl-reversi-rs`) もこれを使っているので、他のツールからも同じように呼び出せます。
+//!
+//! ```
+//! use fl_reversi_rs::{Board, Color, Move};
+//!
+//! let mut board = Board::new();
+//! assert_eq!(board.valid_moves(Color::Black).len(), 4);
+//! board.do_move(Move::Mv("C4".parse().unwrap()), Color::Black);
+//! assert_eq!(board.count_stones(), (4, 1));
+//! ```
+
+pub mod ai_decide;
+pub mod bit_othello;
+pub mod book;
+pub mod command_parser;
+pub mod depth_first_search;
+pub mod eval;
+pub mod game;
+pub mod last_moves;
+pub mod midgame;
+pub mod monte;
+pub mod move_order;
+pub mod probcut;
+pub mod proto;
+pub mod search_stats;
+pub mod self_play;
+pub mod square;
+pub mod stability;
+pub mod symmetry;
+pub mod train;
+pub mod transposition;
+pub mod use_book;
+pub mod zobrist;
+
+pub use bit_othello::{Board, Pos, Position};
+pub use proto::{Color, Move};
+pub use square::Square;
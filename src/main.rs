@@ -1,22 +1,25 @@
-use getopts::Options;
+use getopts::{Options, ParsingStyle};
 use std::collections::HashMap;
 use std::env;
 use std::fmt::Display;
 use std::io::{BufRead, BufReader, BufWriter, Write};
 use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
 use std::process;
+use std::time::Instant;
 use thiserror::Error;
 
-mod ai_decide;
-mod bit_othello;
-mod book;
-mod command_parser;
-mod depth_first_search;
-mod monte;
-mod proto;
-mod use_book;
-use crate::bit_othello::{Board, InitGame};
-use crate::proto::{Color, Move, PlayerStat, RecvCommand, SendCommand, Wl};
+use fl_reversi_rs::ai_decide::Config;
+use fl_reversi_rs::bit_othello::{Board, IllegalMove, InitGame, Position};
+use fl_reversi_rs::eval::{self, LoadWeightsError, Weights};
+use fl_reversi_rs::game::Game;
+use fl_reversi_rs::probcut::{self, LoadProbCutError, ProbCut};
+use fl_reversi_rs::proto::{Color, Move, PlayerStat, RecvCommand, SendCommand, Wl};
+use fl_reversi_rs::search_stats::SearchStats;
+use fl_reversi_rs::train::{self, TrainConfig};
+use fl_reversi_rs::{ai_decide, command_parser, depth_first_search, self_play, use_book};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
 
 #[derive(Debug, Error)]
 enum Error {
@@ -26,13 +29,66 @@ enum Error {
     Parse(String),
     #[error("received invalid command `{0:?}`")]
     Recv(RecvCommand),
+    #[error("board desync: illegal move by {color}: {source}")]
+    IllegalMove { color: Color, source: IllegalMove },
 }
 
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+enum Command {
+    Client,
+    Perft {
+        depth: u32,
+        position: Position,
+    },
+    Analyze {
+        position: Position,
+    },
+    Solve {
+        path: String,
+    },
+    SelfPlay {
+        pairs: u32,
+        first: Config,
+        second: Config,
+        records: Option<String>,
+    },
+    ProbCut {
+        output: String,
+        positions: Option<String>,
+    },
+    Train {
+        output: String,
+        data: Vec<String>,
+    },
+}
+
+/// `analyze` で1局面にかける時間
+const ANALYZE_TIME_MS: i32 = 60_000;
+
+/// `selfplay` の開始局面でランダムに打つ手数
+const SELF_PLAY_OPENING_PLIES: usize = 6;
+
+/// `-w` を指定しなかったときに読む評価関数の重みファイル
+const DEFAULT_WEIGHTS_PATH: &str = "eval.bin";
+
+/// `-m` を指定しなかったときに読む Multi-ProbCut のパラメータファイル
+const DEFAULT_PROBCUT_PATH: &str = "probcut.txt";
+
+/// `probcut` で局面のファイルを指定しなかったときに作るランダムな局面の数
+const PROBCUT_RANDOM_POSITIONS: usize = 200;
+
+/// `probcut` で探索値の組を集める最大の深さ
+const PROBCUT_MAX_DEPTH: u32 = 8;
+
 struct MyOptions {
+    command: Command,
     socket_addr: SocketAddr,
     player: String,
+    /// 対局で使う探索 (`selfplay` では1手にかける時間だけを使います)
+    config: Config,
+    weights: Option<String>,
+    probcut: Option<String>,
     verbose: bool,
 }
 
@@ -79,7 +135,9 @@ enum State {
 }
 
 fn print_usage(program: &str, opts: &Options) -> ! {
-    let brief = format!("Usage: {program} -H HOST -p PORT -n PLAYERNAME");
+    let brief = format!(
+        "Usage: {program} -H HOST -p PORT -n PLAYERNAME\n       {program} perft DEPTH [POSITION]\n       {program} analyze POSITION\n       {program} solve FILE\n       {program} selfplay PAIRS ENGINE ENGINE [RECORDS]\n       {program} probcut OUTPUT [POSITIONS]\n       {program} train OUTPUT DATA..."
+    );
     print!("{}", opts.usage(&brief));
     process::exit(0);
 }
@@ -89,9 +147,35 @@ fn parse_args() -> MyOptions {
     let program = &args[0];
 
     let mut opts = Options::new();
+    // 局面文字列は `-` で始まることがあるので、コマンド以降はオプションとして扱わない
+    opts.parsing_style(ParsingStyle::StopAtFirstFree);
     opts.optopt("H", "host", "set server host", "HOST");
     opts.optopt("p", "port", "set server port", "PORT");
     opts.optopt("n", "name", "set player name", "PLAYERNAME");
+    opts.optopt(
+        "e",
+        "engine",
+        "engine before the endgame: monte, pvs or OPENING,MIDGAME (default: monte)",
+        "ENGINE",
+    );
+    opts.optopt(
+        "t",
+        "time",
+        "set thinking time per move (default: 1400)",
+        "MS",
+    );
+    opts.optopt(
+        "w",
+        "weights",
+        &format!("load evaluation weights (default: {DEFAULT_WEIGHTS_PATH})"),
+        "FILE",
+    );
+    opts.optopt(
+        "m",
+        "probcut",
+        &format!("load Multi-ProbCut parameters (default: {DEFAULT_PROBCUT_PATH})"),
+        "FILE",
+    );
     opts.optflag("v", "verbose", "verbose output");
     opts.optflag("h", "help", "print this help menu");
 
@@ -103,6 +187,68 @@ fn parse_args() -> MyOptions {
         print_usage(program, &opts);
     }
 
+    let command = match matches.free.first().map(String::as_str) {
+        None => Command::Client,
+        Some("perft") => {
+            let depth = match matches.free.get(1).map(|s| s.parse()) {
+                Some(Ok(depth)) => depth,
+                _ => print_usage(program, &opts),
+            };
+            let position = if matches.free.len() > 2 {
+                matches.free[2..].join(" ").parse().unwrap_or_else(|e| {
+                    println!("{e}");
+                    print_usage(program, &opts);
+                })
+            } else {
+                Position {
+                    board: Board::new(),
+                    color: Color::Black,
+                }
+            };
+            Command::Perft { depth, position }
+        }
+        Some("analyze") if matches.free.len() > 1 => {
+            let position = matches.free[1..].join(" ").parse().unwrap_or_else(|e| {
+                println!("{e}");
+                print_usage(program, &opts);
+            });
+            Command::Analyze { position }
+        }
+        Some("solve") if matches.free.len() == 2 => Command::Solve {
+            path: matches.free[1].clone(),
+        },
+        Some("selfplay") if matches.free.len() == 4 || matches.free.len() == 5 => {
+            let parse = |s: &str| {
+                s.parse().unwrap_or_else(|e| {
+                    println!("{e}");
+                    print_usage(program, &opts);
+                })
+            };
+            let pairs = matches.free[1].parse().unwrap_or_else(|e| {
+                println!("{e}");
+                print_usage(program, &opts);
+            });
+            Command::SelfPlay {
+                pairs,
+                first: parse(&matches.free[2]),
+                second: parse(&matches.free[3]),
+                records: matches.free.get(4).cloned(),
+            }
+        }
+        Some("probcut") if matches.free.len() == 2 || matches.free.len() == 3 => Command::ProbCut {
+            output: matches.free[1].clone(),
+            positions: matches.free.get(2).cloned(),
+        },
+        Some("train") if matches.free.len() >= 3 => Command::Train {
+            output: matches.free[1].clone(),
+            data: matches.free[2..].to_vec(),
+        },
+        Some(cmd) => {
+            println!("Unknown command: {cmd}");
+            print_usage(program, &opts);
+        }
+    };
+
     let host = matches
         .opt_str("H")
         .unwrap_or_else(|| "127.0.0.1".to_string());
@@ -115,9 +261,26 @@ fn parse_args() -> MyOptions {
         .next()
         .expect("hostname must be valid");
 
+    let mut config: Config = matches.opt_str("e").map_or_else(Config::default, |s| {
+        s.parse().unwrap_or_else(|e| {
+            println!("{e}");
+            print_usage(program, &opts);
+        })
+    });
+    if let Some(time) = matches.opt_str("t") {
+        config.time_ms = time.parse().unwrap_or_else(|e| {
+            println!("{e}");
+            print_usage(program, &opts);
+        });
+    }
+
     MyOptions {
+        command,
         socket_addr: addr,
         player: matches.opt_str("n").unwrap_or_else(|| "Anon.".to_string()),
+        config,
+        weights: matches.opt_str("w"),
+        probcut: matches.opt_str("m"),
         verbose: matches.opt_present("v"),
     }
 }
@@ -177,20 +340,23 @@ fn my_move(
     reader: &mut BufReader<&TcpStream>,
     writer: &mut BufWriter<&TcpStream>,
     logger: &mut Logger,
-    board: &mut Board,
-    player_color: Color,
+    game: &mut Game,
     assigned_time_ms: &mut i32,
     book_dict: &HashMap<String, String>,
-    kihu: &mut Vec<Move>,
+    config: &Config,
 ) -> Result<State> {
-    let mv = ai_decide::decide(board, player_color, &kihu, &book_dict);
-    kihu.push(mv);
+    let (mv, stats) = ai_decide::decide_with(game, book_dict, config);
     // let mv = board.decide_move(player_color, *assigned_time_ms);
     println!("Your move: {}", mv);
-    board.do_move(mv, player_color);
+    println!("Search: {stats}");
+    if mv != Move::GiveUp {
+        let color = game.side_to_move();
+        game.play(mv)
+            .map_err(|source| Error::IllegalMove { color, source })?;
+    }
 
     send_command(writer, logger, &SendCommand::Move(mv))?;
-    logger.log(board);
+    logger.log(game.board());
 
     match receive_command(reader, logger)? {
         RecvCommand::Ack {
@@ -217,18 +383,21 @@ fn my_move(
 fn op_move(
     reader: &mut BufReader<&TcpStream>,
     logger: &mut Logger,
-    board: &mut Board,
-    player_color: Color,
-    kihu: &mut Vec<Move>,
+    game: &mut Game,
 ) -> Result<State> {
     match receive_command(reader, logger)? {
         RecvCommand::Move(m) => {
             println!("Opponent's move: {}", m);
-            println!("{board}");
-            board.do_move(m, player_color.opposite());
-            print!("{}", board);
-            logger.log(board);
-            kihu.push(m);
+            println!("{}", game.board());
+            if m == Move::GiveUp {
+                // 相手が投了した場合は盤面を変えずに END を待つ
+                return Ok(State::OpTurn(None));
+            }
+            let color = game.side_to_move();
+            game.play(m)
+                .map_err(|source| Error::IllegalMove { color, source })?;
+            print!("{}", game.board());
+            logger.log(game.board());
             Ok(State::MyTurn(None))
         }
         RecvCommand::End {
@@ -284,11 +453,10 @@ fn client(options: &MyOptions) -> Result<()> {
     let book_dict = use_book::initialize_book_dict();
 
     let mut state = State::WaitStart;
-    let mut board = None;
+    let mut game = None;
     let mut assigned_time_ms = 0i32;
     let mut opponent_name = String::new();
     let mut player_color = Color::Black;
-    let mut kihu: Vec<Move> = Vec::new();
     loop {
         match state {
             State::WaitStart => {
@@ -297,38 +465,33 @@ fn client(options: &MyOptions) -> Result<()> {
             State::MyTurn(Some(init_game)) => {
                 assigned_time_ms = init_game.assigned_time_ms;
                 opponent_name = init_game.opponent_name;
-                board = Some(Board::new());
+                game = Some(Game::new());
                 state = State::MyTurn(None);
                 player_color = Color::Black;
-                kihu = Vec::new();
             }
             State::OpTurn(Some(init_game)) => {
                 assigned_time_ms = init_game.assigned_time_ms;
                 opponent_name = init_game.opponent_name;
-                board = Some(Board::new());
+                game = Some(Game::new());
                 state = State::OpTurn(None);
                 player_color = Color::White;
-                kihu = Vec::new();
             }
             State::MyTurn(None) => {
                 state = my_move(
                     &mut reader,
                     &mut writer,
                     &mut logger,
-                    board.as_mut().expect("board must be initialized"),
-                    player_color,
+                    game.as_mut().expect("game must be initialized"),
                     &mut assigned_time_ms,
                     &book_dict,
-                    &mut kihu,
+                    &options.config,
                 )?;
             }
             State::OpTurn(None) => {
                 state = op_move(
                     &mut reader,
                     &mut logger,
-                    board.as_mut().expect("board must be initialized"),
-                    player_color,
-                    &mut kihu,
+                    game.as_mut().expect("game must be initialized"),
                 )?;
             }
             State::EndGame {
@@ -357,9 +520,323 @@ fn client(options: &MyOptions) -> Result<()> {
     Ok(())
 }
 
+fn perft(depth: u32, position: &Position) {
+    println!("{position}");
+    let mut total = 0;
+    for d in 1..=depth {
+        let start = Instant::now();
+        let count = position.board.perft(position.color, d);
+        let elapsed = start.elapsed();
+        total += count;
+        println!(
+            "perft({d}) = {count} ({:.3}s, {:.0} nodes/s)",
+            elapsed.as_secs_f64(),
+            count as f64 / elapsed.as_secs_f64()
+        );
+    }
+    println!("total: {total}");
+}
+
+fn analyze(position: &Position) {
+    println!("{position}");
+    let analysis = depth_first_search::analyze(&position.board, position.color, ANALYZE_TIME_MS);
+    for score in &analysis.moves {
+        match score.exact() {
+            Some(diff) => println!("{}: {diff:+}", score.mv),
+            None => println!(
+                "{}: [{:+}, {:+}] ({:?})",
+                score.mv,
+                score.lower,
+                score.upper,
+                score.proof()
+            ),
+        }
+    }
+    println!("{}", analysis.stats);
+}
+
+/// `solve` のファイルの1行 (局面と、あれば期待する石差)
+struct SolveCase {
+    line: usize,
+    position: Position,
+    expected: Option<i32>,
+}
+
+/// `solve` のファイルを読みます。
+///
+/// 1行に1局面を `局面 手番 [石差]` の形で書きます。`#` から行末まではコメントです。
+fn read_solve_cases(path: &str) -> Result<Vec<SolveCase>> {
+    let file = std::fs::File::open(path)?;
+    let mut cases = vec![];
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let content = line.split('#').next().unwrap_or_default();
+        let fields: Vec<&str> = content.split_whitespace().collect();
+        if fields.is_empty() {
+            continue;
+        }
+        let parse_error = |e: &dyn Display| Error::Parse(format!("{path}:{}: {e}", i + 1));
+        if fields.len() < 2 || fields.len() > 3 {
+            return Err(parse_error(&"expected `POSITION SIDE [SCORE]`"));
+        }
+        let position = format!("{} {}", fields[0], fields[1])
+            .parse()
+            .map_err(|e| parse_error(&e))?;
+        let expected = fields
+            .get(2)
+            .map(|s| s.parse())
+            .transpose()
+            .map_err(|e| parse_error(&e))?;
+        cases.push(SolveCase {
+            line: i + 1,
+            position,
+            expected,
+        });
+    }
+    Ok(cases)
+}
+
+/// ファイルの局面を全て読み切り、期待する石差と一致しない局面があれば `false` を返します。
+fn solve(path: &str) -> Result<bool> {
+    let cases = read_solve_cases(path)?;
+    let mut total = SearchStats::default();
+    let mut failures = 0;
+    for case in &cases {
+        let position = &case.position;
+        let solution = depth_first_search::perfect_read(&position.board, position.color, i32::MAX);
+        let result = match case.expected {
+            Some(expected) if expected != solution.value => {
+                failures += 1;
+                format!(" (expected {expected:+}) FAILED")
+            }
+            Some(_) => " ok".to_string(),
+            None => String::new(),
+        };
+        println!(
+            "line {}: {} {:+}{result}  {}",
+            case.line, solution.best_move, solution.value, solution.stats
+        );
+        total.merge(&solution.stats);
+    }
+    println!("total: {} positions, {total}", cases.len());
+    if failures > 0 {
+        println!("{failures} positions FAILED");
+    }
+    Ok(failures == 0)
+}
+
+/// `first` と `second` で対局させ、`first` から見た成績を表示します。
+///
+/// `records` を指定すると、終局した対局の棋譜を `train` で読める形で書き出します。
+fn self_play(pairs: u32, first: &Config, second: &Config, records: Option<&str>) -> Result<()> {
+    println!("{first} vs {second}, {} ms per move", first.time_ms);
+    let mut records = match records {
+        Some(path) => Some(BufWriter::new(std::fs::File::create(path)?)),
+        None => None,
+    };
+    let book_dict = use_book::initialize_book_dict();
+    let seed = rand::random();
+    let mut write_result = Ok(());
+    let result = self_play::run_match(
+        first,
+        second,
+        pairs,
+        SELF_PLAY_OPENING_PLIES,
+        seed,
+        &book_dict,
+        |result, game| {
+            println!("game {}: {result}", result.games());
+            if let (Some(records), Ok(())) = (records.as_mut(), &write_result) {
+                write_result = writeln!(records, "{}", train::format_record(game));
+            }
+        },
+    );
+    write_result?;
+    if let Some(mut records) = records {
+        records.flush()?;
+    }
+    println!("{first} vs {second}: {result}");
+    Ok(())
+}
+
+/// 評価関数の重みを読み込みます。
+///
+/// `-w` で指定したファイルが読めなければ終了します。指定がなく既定のファイルもなければ、
+/// 組み込みの重みを使います。
+fn load_weights(path: Option<&str>) {
+    let weights = match path {
+        Some(path) => Weights::load(path).unwrap_or_else(|e| {
+            eprintln!("{path}: {e}");
+            process::exit(1);
+        }),
+        None => match Weights::load(DEFAULT_WEIGHTS_PATH) {
+            Ok(weights) => weights,
+            Err(LoadWeightsError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => {
+                eprintln!("{DEFAULT_WEIGHTS_PATH}: {e}, using built-in weights");
+                return;
+            }
+        },
+    };
+    eval::install(weights);
+}
+
+/// Multi-ProbCut のパラメータを読み込みます。
+///
+/// `-m` で指定したファイルが読めなければ終了します。指定がなく既定のファイルもなければ、
+/// 枝刈りしません。
+fn load_probcut(path: Option<&str>) {
+    let probcut = match path {
+        Some(path) => ProbCut::load(path).unwrap_or_else(|e| {
+            eprintln!("{path}: {e}");
+            process::exit(1);
+        }),
+        None => match ProbCut::load(DEFAULT_PROBCUT_PATH) {
+            Ok(probcut) => probcut,
+            Err(LoadProbCutError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => {
+                eprintln!("{DEFAULT_PROBCUT_PATH}: {e}, probcut disabled");
+                return;
+            }
+        },
+    };
+    probcut::install(probcut);
+}
+
+/// Multi-ProbCut のパラメータを求めて `output` に書き出します。
+///
+/// `positions` は `solve` と同じ形式の局面のファイルで (石差は使いません)、
+/// 指定しなければランダムに打ち進めた局面を使います。
+fn fit_probcut(output: &str, positions: Option<&str>) -> Result<()> {
+    let positions: Vec<Position> = match positions {
+        Some(path) => read_solve_cases(path)?
+            .into_iter()
+            .map(|case| case.position)
+            .collect(),
+        None => {
+            let mut rng = StdRng::seed_from_u64(rand::random());
+            (0..PROBCUT_RANDOM_POSITIONS)
+                .map(|_| {
+                    let plies = rng.gen_range(4..50);
+                    let mut game = Game::new();
+                    for m in self_play::random_opening(&mut rng, plies) {
+                        game.play(m).expect("random opening must be legal");
+                    }
+                    game.position()
+                })
+                .collect()
+        }
+    };
+    println!(
+        "collecting from {} positions (depth {}..={PROBCUT_MAX_DEPTH})",
+        positions.len(),
+        probcut::MIN_DEPTH
+    );
+    let start = Instant::now();
+    let samples: Vec<_> = positions
+        .par_iter()
+        .flat_map_iter(|position| {
+            probcut::collect(
+                &position.board,
+                position.color,
+                eval::weights(),
+                PROBCUT_MAX_DEPTH,
+            )
+        })
+        .collect();
+    println!(
+        "{} samples in {:.1}s",
+        samples.len(),
+        start.elapsed().as_secs_f64()
+    );
+    let probcut = ProbCut::fit(&samples, probcut::DEFAULT_THRESHOLD);
+    for (phase, depth, cut) in probcut.iter() {
+        println!(
+            "phase {phase:2} depth {depth:2} <- {}: a = {:.3}, b = {:+.1}, sigma = {:.1}",
+            cut.shallow_depth, cut.a, cut.b, cut.sigma
+        );
+    }
+    probcut.save(output)?;
+    println!("wrote {output}");
+    Ok(())
+}
+
+/// `data` のファイルの局面で評価関数の重みを学習し、`output` に書き出します。
+///
+/// 読み込んだ重み (なければ組み込みの重み) から始めます。
+fn train(output: &str, data: &[String]) -> Result<()> {
+    let mut samples = vec![];
+    for path in data {
+        let file = std::fs::File::open(path)?;
+        let read = train::read_samples(BufReader::new(file))
+            .map_err(|e| Error::Parse(format!("{path}: {e}")))?;
+        println!("{path}: {} positions", read.len());
+        samples.extend(read);
+    }
+    let mut weights = eval::weights().clone();
+    let config = TrainConfig::default();
+    let start = Instant::now();
+    let reports = train::train(&mut weights, &samples, &config);
+    println!(
+        "trained on {} positions in {:.1}s",
+        samples.len(),
+        start.elapsed().as_secs_f64()
+    );
+    for report in reports {
+        println!(
+            "phase {:2}: {:7} positions, rmse {:5.2} -> {:5.2} discs",
+            report.phase, report.samples, report.rmse_before, report.rmse_after
+        );
+    }
+    weights.save(output)?;
+    println!("wrote {output}");
+    Ok(())
+}
+
 fn main() {
     let options = parse_args();
-    client(&options).unwrap_or_else(|e| {
-        eprintln!("{e}");
-    });
+    load_weights(options.weights.as_deref());
+    load_probcut(options.probcut.as_deref());
+    match options.command {
+        Command::Client => client(&options).unwrap_or_else(|e| {
+            eprintln!("{e}");
+        }),
+        Command::Perft { depth, position } => perft(depth, &position),
+        Command::Analyze { position } => analyze(&position),
+        Command::SelfPlay {
+            pairs,
+            first,
+            second,
+            records,
+        } => {
+            let time_ms = options.config.time_ms;
+            self_play(
+                pairs,
+                &Config { time_ms, ..first },
+                &Config { time_ms, ..second },
+                records.as_deref(),
+            )
+            .unwrap_or_else(|e| {
+                eprintln!("{e}");
+                process::exit(1);
+            });
+        }
+        Command::ProbCut { output, positions } => fit_probcut(&output, positions.as_deref())
+            .unwrap_or_else(|e| {
+                eprintln!("{e}");
+                process::exit(1);
+            }),
+        Command::Train { output, data } => train(&output, &data).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            process::exit(1);
+        }),
+        Command::Solve { path } => match solve(&path) {
+            Ok(true) => {}
+            Ok(false) => process::exit(1),
+            Err(e) => {
+                eprintln!("{e}");
+                process::exit(1);
+            }
+        },
+    }
 }
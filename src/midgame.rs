@@ -0,0 +1,622 @@
+//! 中盤の探索 (深さを決めて評価関数で打ち切る alpha-beta) です。
+//!
+//! 反復深化の PVS (principal variation search) で、1手目を普通の窓で読み、
+//! 残りの手は null window で最善手を超えないことだけを確かめます。
+//! 2回目以降の反復は前の深さの値を中心にした狭い窓 (aspiration window) から始め、
+//! 窓を外れたら広げて読み直します。
+//!
+//! 手は置換表の最善手、キラー手 (同じ手数で枝刈りを起こした手)、
+//! ヒストリー (枝刈りを起こした回数を深さで重み付けしたもの) の順に並べます。
+//!
+//! パラメータを渡せば、null window の局面で Multi-ProbCut ([`crate::probcut`]) による枝刈りもします。
+
+use crate::bit_othello::{Board, Flips, Squares};
+use crate::eval::{self, Evaluator};
+use crate::probcut::{self, ProbCut};
+use crate::proto::{Color, Move};
+use crate::search_stats::{SearchCounter, SearchStats};
+use crate::square::Square;
+use crate::transposition::{Bound, Entry, TranspositionTable};
+use crate::zobrist;
+use std::time::Instant;
+
+/// 中盤探索の置換表のバケット数
+const TABLE_BUCKETS: usize = 1 << 19;
+
+/// キラー手を覚える手数
+const MAX_PLY: usize = 128;
+
+/// aspiration window の最初の幅 (評価関数の単位)
+const ASPIRATION_WIDTH: i32 = 4;
+
+const TT_MOVE_SCORE: u32 = u32::MAX;
+const KILLER_SCORE: [u32; 2] = [u32::MAX - 1, u32::MAX - 2];
+
+/// 深さを決めた探索器
+///
+/// 返り値は手番側から見た評価値 (評価関数の単位、終局なら石差 × 単位) で、
+/// 窓の外側の値は上界・下界になります (fail-soft)。
+pub struct Searcher<'a> {
+    tt: &'a TranspositionTable,
+    evaluator: &'a dyn Evaluator,
+    counter: SearchCounter,
+    killers: [[Option<Square>; 2]; MAX_PLY],
+    /// 手番 (黒、白) とマスごとのヒストリー
+    history: [[u32; 64]; 2],
+    probcut: Option<&'a ProbCut>,
+    /// Multi-ProbCut の浅い探索の中かどうか (浅い値で深い値を上書きしないよう、置換表に保存しません)
+    in_probcut: bool,
+}
+
+impl<'a> Searcher<'a> {
+    pub fn new(
+        tt: &'a TranspositionTable,
+        evaluator: &'a dyn Evaluator,
+        start_time: Instant,
+        assigned_time_ms: i32,
+    ) -> Self {
+        Self {
+            tt,
+            evaluator,
+            counter: SearchCounter::new(start_time, assigned_time_ms),
+            killers: [[None; 2]; MAX_PLY],
+            history: [[0; 64]; 2],
+            probcut: None,
+            in_probcut: false,
+        }
+    }
+
+    /// Multi-ProbCut のパラメータを設定します (`None` なら枝刈りしません)。
+    pub fn with_probcut(mut self, probcut: Option<&'a ProbCut>) -> Self {
+        self.probcut = probcut;
+        self
+    }
+
+    /// 探索したノード数
+    pub fn nodes(&self) -> u64 {
+        self.counter.nodes
+    }
+
+    /// この探索器の統計 (経過時間と深さは呼び出し側で埋めます)
+    pub fn stats(&self) -> SearchStats {
+        self.counter.stats()
+    }
+
+    /// 評価値の上限 (石差 64 に当たる値)
+    pub fn score_limit(&self) -> i32 {
+        64 * self.evaluator.unit()
+    }
+
+    /// `color` の手番で `depth` 手先まで `(alpha, beta)` の窓で読みます。時間切れなら `None` を返します。
+    pub fn search(
+        &mut self,
+        board: &mut Board,
+        color: Color,
+        depth: u32,
+        alpha: i32,
+        beta: i32,
+    ) -> Option<i32> {
+        let hash = zobrist::hash_with_color(board, color);
+        self.pvs(board, color, hash, depth, alpha, beta, 0, false)
+    }
+
+    /// `hash` は `board` と `color` のZobristハッシュ、`ply` は探索の開始からの手数
+    #[allow(clippy::too_many_arguments)]
+    fn pvs(
+        &mut self,
+        board: &mut Board,
+        color: Color,
+        hash: u64,
+        depth: u32,
+        alpha: i32,
+        beta: i32,
+        ply: usize,
+        passed: bool,
+    ) -> Option<i32> {
+        if self.counter.check_timeout() {
+            return None;
+        }
+        self.counter.nodes += 1;
+        let unit = self.evaluator.unit();
+        if board.sum_stones() == 64 {
+            return Some(board.diff_stones(color) * unit);
+        }
+        if depth == 0 {
+            let limit = self.score_limit();
+            return Some(self.evaluator.evaluate(board, color).clamp(-limit, limit));
+        }
+        let can_put = board.can_put(color);
+        if can_put == 0 {
+            if passed {
+                return Some(board.diff_stones(color) * unit);
+            }
+            return self
+                .pvs(
+                    board,
+                    color.opposite(),
+                    hash ^ zobrist::WHITE_TO_MOVE,
+                    depth,
+                    -beta,
+                    -alpha,
+                    ply + 1,
+                    true,
+                )
+                .map(|v| -v);
+        }
+
+        let (mut alpha, mut beta) = (alpha, beta);
+        let mut tt_move = None;
+        self.counter.tt.probes += 1;
+        if let Some(entry) = self.tt.probe(hash) {
+            self.counter.tt.hits += 1;
+            if entry.depth as u32 >= depth {
+                if let Some(value) = entry.cutoff(alpha, beta) {
+                    return Some(value);
+                }
+                match entry.bound {
+                    Bound::Lower => alpha = alpha.max(entry.score),
+                    Bound::Upper => beta = beta.min(entry.score),
+                    Bound::Exact => {}
+                }
+            }
+            tt_move = entry.best_move;
+        }
+
+        if let Some(value) = self.probcut(board, color, hash, depth, alpha, beta, ply) {
+            return Some(value);
+        }
+
+        let alpha_orig = alpha;
+        let mut best = i32::MIN;
+        let mut best_move = None;
+        for (i, (square, flips)) in self
+            .ordered_moves(board, color, can_put, tt_move, ply)
+            .into_iter()
+            .enumerate()
+        {
+            let child_hash = zobrist::update(hash, square, flips, color) ^ zobrist::WHITE_TO_MOVE;
+            board.make(square, flips, color);
+            let value = self.search_child(
+                board,
+                color.opposite(),
+                child_hash,
+                depth - 1,
+                alpha,
+                beta,
+                ply + 1,
+                i == 0,
+            );
+            board.unmake(square, flips, color);
+            let value = value?;
+            if value > best {
+                best = value;
+                best_move = Some(square);
+                if best > alpha {
+                    alpha = best;
+                    if alpha >= beta {
+                        self.record_cutoff(color, square, depth, ply);
+                        break;
+                    }
+                }
+            }
+        }
+        if !self.in_probcut {
+            self.counter.tt.stores += 1;
+            self.tt.store(
+                hash,
+                Entry {
+                    score: best,
+                    bound: Bound::classify(best, alpha_orig, beta),
+                    best_move,
+                    depth: depth as u8,
+                },
+            );
+        }
+        Some(best)
+    }
+
+    /// 打った後の局面を読み、打った側から見た値を返します。
+    ///
+    /// 1手目 (`first`) 以外は null window で読み、`alpha` を超えたときだけ窓を広げて読み直します。
+    #[allow(clippy::too_many_arguments)]
+    fn search_child(
+        &mut self,
+        board: &mut Board,
+        color: Color,
+        hash: u64,
+        depth: u32,
+        alpha: i32,
+        beta: i32,
+        ply: usize,
+        first: bool,
+    ) -> Option<i32> {
+        if first {
+            return self
+                .pvs(board, color, hash, depth, -beta, -alpha, ply, false)
+                .map(|v| -v);
+        }
+        let value = -self.pvs(board, color, hash, depth, -alpha - 1, -alpha, ply, false)?;
+        if value > alpha && value < beta {
+            return self
+                .pvs(board, color, hash, depth, -beta, -value, ply, false)
+                .map(|v| -v);
+        }
+        Some(value)
+    }
+
+    /// Multi-ProbCut: 浅い探索から深い探索値が窓の外に出ると推定できれば、窓の端を返します。
+    ///
+    /// 浅い探索が時間切れになったときは枝刈りせず、呼び出し側の探索で時間切れを返します。
+    #[allow(clippy::too_many_arguments)]
+    fn probcut(
+        &mut self,
+        board: &mut Board,
+        color: Color,
+        hash: u64,
+        depth: u32,
+        alpha: i32,
+        beta: i32,
+        ply: usize,
+    ) -> Option<i32> {
+        let probcut = self.probcut?;
+        if depth < probcut::MIN_DEPTH || beta - alpha != 1 {
+            return None;
+        }
+        let cut = *probcut.get(eval::phase(board), depth)?;
+        if cut.a <= 0.0 {
+            return None;
+        }
+        let margin = probcut.threshold * cut.sigma;
+        let limit = self.score_limit();
+        let shallow = cut.shallow_depth;
+
+        // 浅い探索値が bound 以上なら、深い探索値は beta 以上と推定できる
+        let bound = ((beta as f64 + margin - cut.b) / cut.a).ceil() as i32;
+        if bound <= limit {
+            let value = self.shallow_search(board, color, hash, shallow, bound - 1, bound, ply)?;
+            if value >= bound {
+                return Some(beta);
+            }
+        }
+        // 浅い探索値が bound 以下なら、深い探索値は alpha 以下と推定できる
+        let bound = ((alpha as f64 - margin - cut.b) / cut.a).floor() as i32;
+        if bound >= -limit {
+            let value = self.shallow_search(board, color, hash, shallow, bound, bound + 1, ply)?;
+            if value <= bound {
+                return Some(alpha);
+            }
+        }
+        None
+    }
+
+    /// Multi-ProbCut の浅い探索です。置換表は引きますが、結果は保存しません。
+    #[allow(clippy::too_many_arguments)]
+    fn shallow_search(
+        &mut self,
+        board: &mut Board,
+        color: Color,
+        hash: u64,
+        depth: u32,
+        alpha: i32,
+        beta: i32,
+        ply: usize,
+    ) -> Option<i32> {
+        let outer = std::mem::replace(&mut self.in_probcut, true);
+        let value = self.pvs(board, color, hash, depth, alpha, beta, ply, false);
+        self.in_probcut = outer;
+        value
+    }
+
+    /// 置換表の手、キラー手、ヒストリーの順に並べます。
+    fn ordered_moves(
+        &self,
+        board: &Board,
+        color: Color,
+        can_put: u64,
+        tt_move: Option<Square>,
+        ply: usize,
+    ) -> Vec<(Square, Flips)> {
+        let killers = self.killers[ply.min(MAX_PLY - 1)];
+        let history = &self.history[zobrist::color_index(color)];
+        let mut moves: Vec<(Square, Flips)> = Squares(can_put)
+            .map(|square| (square, board.flips(square, color)))
+            .collect();
+        moves.sort_by_cached_key(|&(square, _)| {
+            let score = if Some(square) == tt_move {
+                TT_MOVE_SCORE
+            } else if Some(square) == killers[0] {
+                KILLER_SCORE[0]
+            } else if Some(square) == killers[1] {
+                KILLER_SCORE[1]
+            } else {
+                history[square.index()]
+            };
+            std::cmp::Reverse(score)
+        });
+        moves
+    }
+
+    /// 枝刈りを起こした手をキラー手とヒストリーに覚えます。
+    fn record_cutoff(&mut self, color: Color, square: Square, depth: u32, ply: usize) {
+        let killers = &mut self.killers[ply.min(MAX_PLY - 1)];
+        if killers[0] != Some(square) {
+            killers[1] = killers[0];
+            killers[0] = Some(square);
+        }
+        let history = &mut self.history[zobrist::color_index(color)][square.index()];
+        *history = history
+            .saturating_add(depth * depth)
+            .min(KILLER_SCORE[1] - 1);
+    }
+
+    /// ルートの手を `moves` の順に読み、最善手と値を返します。最善手は `moves` の先頭に移します。
+    fn search_root(
+        &mut self,
+        board: &mut Board,
+        color: Color,
+        moves: &mut [(Square, Flips)],
+        depth: u32,
+        alpha: i32,
+        beta: i32,
+    ) -> Option<(Square, i32)> {
+        let hash = zobrist::hash_with_color(board, color);
+        let mut alpha = alpha;
+        let mut best = (moves[0].0, i32::MIN);
+        let mut best_index = 0;
+        for (i, &(square, flips)) in moves.iter().enumerate() {
+            let child_hash = zobrist::update(hash, square, flips, color) ^ zobrist::WHITE_TO_MOVE;
+            board.make(square, flips, color);
+            let value = self.search_child(
+                board,
+                color.opposite(),
+                child_hash,
+                depth - 1,
+                alpha,
+                beta,
+                1,
+                i == 0,
+            );
+            board.unmake(square, flips, color);
+            let value = value?;
+            if value > best.1 {
+                best = (square, value);
+                best_index = i;
+                alpha = alpha.max(value);
+                if alpha >= beta {
+                    break;
+                }
+            }
+        }
+        moves[..=best_index].rotate_right(1);
+        Some(best)
+    }
+}
+
+/// 中盤探索の結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchResult {
+    pub best_move: Move,
+    /// 最後に読み終えた深さでの評価値 (評価関数の単位)
+    pub value: i32,
+    pub stats: SearchStats,
+}
+
+/// `assigned_time_ms` の間、反復深化で読んで手を決めます。
+///
+/// 時間切れになった反復は捨てて、最後に読み終えた深さの最善手を返します。
+/// 評価値は評価関数によって変わるので、置換表は読み切りと共有せず、呼ぶたびに作ります。
+pub fn decide(
+    board: &Board,
+    color: Color,
+    assigned_time_ms: i32,
+    evaluator: &dyn Evaluator,
+) -> SearchResult {
+    decide_with(board, color, assigned_time_ms, evaluator, None, None)
+}
+
+/// [`decide`] と同じですが、`probcut` のパラメータで Multi-ProbCut の枝刈りをします。
+///
+/// パラメータは `evaluator` と同じ評価関数で求めたものを渡してください。
+/// `hint` を渡すと、その手から読みます (最初の深さも読み終わらなければ `hint` を返します)。
+pub fn decide_with(
+    board: &Board,
+    color: Color,
+    assigned_time_ms: i32,
+    evaluator: &dyn Evaluator,
+    probcut: Option<&ProbCut>,
+    hint: Option<Square>,
+) -> SearchResult {
+    let start_time = Instant::now();
+    let tt = TranspositionTable::new(TABLE_BUCKETS);
+    let mut searcher =
+        Searcher::new(&tt, evaluator, start_time, assigned_time_ms).with_probcut(probcut);
+    let mut board = board.clone();
+    let mut moves = searcher.ordered_moves(&board, color, board.can_put(color), hint, 0);
+    if moves.is_empty() {
+        return SearchResult {
+            best_move: Move::Pass,
+            value: 0,
+            stats: SearchStats::default(),
+        };
+    }
+
+    let limit = searcher.score_limit() + 1;
+    let width = ASPIRATION_WIDTH * evaluator.unit();
+    let mut best = (moves[0].0, 0);
+    let mut completed = 0;
+    let empties = 64 - board.sum_stones() as u32;
+    'deepening: for depth in 1..=empties {
+        // 時間を調べるのは何ノードかおきなので、時間がなければ最初の深さも始めない
+        if start_time.elapsed().as_millis() >= assigned_time_ms.max(0) as u128 {
+            break;
+        }
+        let mut delta = width;
+        let (mut alpha, mut beta) = if depth == 1 {
+            (-limit, limit)
+        } else {
+            ((best.1 - delta).max(-limit), (best.1 + delta).min(limit))
+        };
+        loop {
+            let Some((square, value)) =
+                searcher.search_root(&mut board, color, &mut moves, depth, alpha, beta)
+            else {
+                break 'deepening;
+            };
+            delta *= 2;
+            if value <= alpha && alpha > -limit {
+                alpha = (value - delta).max(-limit);
+            } else if value >= beta && beta < limit {
+                beta = (value + delta).min(limit);
+            } else {
+                best = (square, value);
+                break;
+            }
+        }
+        completed = depth;
+        // 次の深さは今の深さより時間がかかるので、残り時間が半分を切ったら始めない
+        if start_time.elapsed().as_millis() * 2 > assigned_time_ms as u128 {
+            break;
+        }
+    }
+
+    let mut stats = searcher.stats();
+    stats.elapsed = start_time.elapsed();
+    stats.depth = completed;
+    SearchResult {
+        best_move: Move::Mv(best.0),
+        value: best.1,
+        stats,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_othello;
+    use crate::depth_first_search;
+    use crate::eval::{DiscCount, Mobility, Weights, SCALE};
+    use crate::game::Game;
+
+    /// 手番側に打てる手がある、空きマスが `empties` 個の局面
+    fn random_position(empties: u64) -> (Board, Color) {
+        loop {
+            let (board, color) = bit_othello::random_position(empties);
+            if !board.valid_moves(color).is_empty() {
+                return (board, color);
+            }
+        }
+    }
+
+    #[test]
+    fn test_full_depth_is_exact() {
+        let weights = Weights::fallback();
+        for _ in 0..5 {
+            let (board, color) = random_position(8);
+            let exact = depth_first_search::perfect_read(&board, color, 10000).value;
+            let tt = TranspositionTable::new(1024);
+            let mut searcher = Searcher::new(&tt, &weights, Instant::now(), 10000);
+            let limit = searcher.score_limit() + 1;
+            let value = searcher.search(&mut board.clone(), color, 8, -limit, limit);
+            assert_eq!(value, Some(exact * SCALE));
+
+            // 窓の外の値は正しい向きの上界・下界になる
+            for n in [-10, 0, 10] {
+                let (alpha, beta) = (n * SCALE - 1, n * SCALE);
+                let value = searcher
+                    .search(&mut board.clone(), color, 8, alpha, beta)
+                    .unwrap();
+                if value <= alpha {
+                    assert!(exact * SCALE <= value);
+                } else {
+                    assert!(exact * SCALE >= value);
+                }
+            }
+
+            // 終局まで読めば、どの評価関数でも最善手は読み切りの値になる
+            let result = decide(&board, color, 10000, &DiscCount);
+            assert_eq!(result.value, exact);
+            assert_eq!(result.stats.depth, 8);
+            let mut after = board.clone();
+            after.do_move(result.best_move, color);
+            let value = -depth_first_search::perfect_read(&after, color.opposite(), 10000).value;
+            assert_eq!(value, exact);
+        }
+    }
+
+    #[test]
+    fn test_probcut() {
+        let weights = Weights::fallback();
+        let (board, color) = random_position(36);
+        let search = |probcut: Option<&ProbCut>| {
+            let tt = TranspositionTable::new(1 << 12);
+            let mut searcher =
+                Searcher::new(&tt, &weights, Instant::now(), 10000).with_probcut(probcut);
+            let limit = searcher.score_limit() + 1;
+            let value = searcher.search(&mut board.clone(), color, 7, -limit, limit);
+            (value.unwrap(), searcher.nodes())
+        };
+        let with_threshold = |threshold| {
+            let mut probcut = ProbCut::new(threshold);
+            for phase in 0..eval::NUM_PHASES {
+                for depth in probcut::MIN_DEPTH..=probcut::MAX_DEPTH {
+                    let cut = probcut::Cut {
+                        shallow_depth: probcut::shallow_depth(depth),
+                        a: 1.0,
+                        b: 0.0,
+                        sigma: SCALE as f64,
+                    };
+                    probcut.set(phase, depth, cut);
+                }
+            }
+            probcut
+        };
+        let (value, nodes) = search(None);
+        // 余裕が大きすぎて一度も切れなければ、値もノード数も変わらない
+        assert_eq!(search(Some(&with_threshold(1000.0))), (value, nodes));
+        // 余裕がなければよく切れる
+        assert!(search(Some(&with_threshold(0.0))).1 < nodes);
+
+        // 浅い探索の結果は置換表に残さない
+        let tt = TranspositionTable::new(1 << 12);
+        let mut searcher = Searcher::new(&tt, &weights, Instant::now(), 10000);
+        let hash = zobrist::hash_with_color(&board, color);
+        let limit = searcher.score_limit() + 1;
+        searcher
+            .shallow_search(&mut board.clone(), color, hash, 3, -limit, limit, 0)
+            .unwrap();
+        assert_eq!(searcher.stats().tt.stores, 0);
+        assert!(tt.probe(hash).is_none());
+        searcher.search(&mut board.clone(), color, 3, -limit, limit);
+        assert!(searcher.stats().tt.stores > 0);
+        assert!(tt.probe(hash).is_some());
+    }
+
+    #[test]
+    fn test_decide_returns_legal_move() {
+        let board = Board::new();
+        let result = decide(&board, Color::Black, 100, &Mobility);
+        assert!(board.check_valid_move(result.best_move, Color::Black));
+        assert!(result.stats.depth >= 1 && result.stats.nodes > 0);
+
+        // 時間がなくても合法手を返す
+        let result = decide(&board, Color::Black, 0, &Mobility);
+        assert!(board.check_valid_move(result.best_move, Color::Black));
+    }
+
+    #[test]
+    fn test_decide_starts_from_hint() {
+        let mut game = Game::new();
+        for m in ["F5", "D6", "C3"] {
+            game.play(Move::Mv(m.parse().unwrap())).unwrap();
+        }
+        let (board, color) = (game.board(), game.side_to_move());
+        let moves = board.valid_moves(color);
+        assert!(moves.len() > 1);
+        // 時間がなければ、どの手を渡しても (最善手でなくても) `hint` を返す
+        for &hint in &moves {
+            let result = decide_with(board, color, 0, &Mobility, None, Some(hint));
+            assert_eq!(result.best_move, Move::Mv(hint));
+            assert_eq!(result.stats.depth, 0);
+        }
+    }
+}
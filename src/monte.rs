@@ -1,7 +1,10 @@
 use super::proto::{Color, Move};
 use rand::Rng;
 
-use crate::bit_othello::{get_corner_list, Board};
+use crate::bit_othello::{bit_count, Board, Squares, CORNERS};
+use crate::eval::{Evaluator, Mobility};
+use crate::search_stats::SearchStats;
+use crate::square::Square;
 
 fn minus_tuple(a: (i32, i32)) -> (i32, i32) {
     (-a.0, -a.1)
@@ -18,21 +21,27 @@ pub struct MonteNode {
     pub color: Color,
     // pub parent: Option<Box<MonteNode>>,
     pub children: Vec<MonteNode>,
-    pub is_expanded: bool,  // 展開済みかどうか
-    pub wins: i32,          // 勝利数
-    pub visits: i32,        // このノードを調べた回数
-    pub is_game_end: bool,  // ゲーム終了ノードかどうか
-    pub prev_is_skip: bool, // 1つ前の手がパスかどうか
+    pub is_expanded: bool, // 展開済みかどうか
+    pub wins: i32,         // 勝利数
+    pub visits: i32,       // このノードを調べた回数
+    pub is_game_end: bool, // ゲーム終了ノードかどうか
     // pub is_root: bool,      // ルートノードかどうか
     pub prev_move: Option<Move>,
-    pub canput_diff: i32,
+    /// 手番側から見た評価値 (評価関数の単位で割ったもの)
+    pub score: i32,
     pub mobility: i32,
     pub stone_sum: u64,
 }
 
 impl MonteNode {
-    pub fn new(board: Board, color: Color, prev_move: Option<Move>, stone_sum: u64) -> Self {
-        let canput_diff = board.canput_diff(color);
+    pub fn new(
+        board: Board,
+        color: Color,
+        prev_move: Option<Move>,
+        stone_sum: u64,
+        evaluator: &dyn Evaluator,
+    ) -> Self {
+        let score = evaluator.evaluate(&board, color) / evaluator.unit();
         Self {
             board,
             color,
@@ -41,24 +50,19 @@ impl MonteNode {
             wins: 0,
             visits: 0,
             is_game_end: false,
-            prev_is_skip: false,
-            prev_move: prev_move,
-            canput_diff: canput_diff,
+            prev_move,
+            score,
             mobility: 0,
-            stone_sum: stone_sum,
+            stone_sum,
         }
     }
 
-    pub fn play_out(&mut self) -> (i32, i32) {
+    pub fn play_out(&mut self, evaluator: &dyn Evaluator) -> (i32, i32) {
         let mut rng = rand::thread_rng();
         let mut board = self.board.clone();
-        let mut color = self.color;
-        let mut is_passed = false;
         // self.visits += 1;
-        if !self.is_expanded {
-            if self.visits > EXPAND_THRESHOLD {
-                self.expand();
-            }
+        if !self.is_expanded && self.visits > EXPAND_THRESHOLD {
+            self.expand(evaluator);
         }
         if self.is_expanded {
             let mut max_ucb = 0.0;
@@ -74,14 +78,13 @@ impl MonteNode {
                 let ucb = if child.visits == 0 {
                     f64::INFINITY
                 } else {
-                    let ucb = calculate_ucb(
+                    calculate_ucb(
                         child.wins,
                         child.visits,
                         self.visits,
                         child.mobility,
                         child.stone_sum,
-                    );
-                    ucb
+                    )
                 };
                 if ucb > max_ucb {
                     max_ucb = ucb;
@@ -89,51 +92,37 @@ impl MonteNode {
                 }
             }
             let child = &mut self.children[max_ucb_index];
-            let result = minus_tuple(child.play_out());
+            let result = minus_tuple(child.play_out(evaluator));
             // self.wins += result.0;
             // self.mobility += result.1;
             self.add(result.0, result.1);
             return result;
         }
-        loop {
-            let moves = board.valid_moves(color);
-            if moves.is_empty() {
-                if is_passed {
-                    break;
-                }
-                is_passed = true;
-                color = color.opposite();
-                continue;
-            }
-            is_passed = false;
-            // let m = moves[rng.gen_range(0..moves.len())];
-            let moves_len = moves.len();
-            let m = if moves_len == 1 {
-                moves[0]
+        let mut next = board.side_to_play(self.color);
+        while let Some(color) = next {
+            let can_put = board.can_put(color);
+            let moves_len = bit_count(can_put);
+            let square = if moves_len == 1 {
+                nth_square(can_put, 0)
             } else {
-                let corner_list = get_corner_list(&moves);
-                if !corner_list.is_empty() && rng.gen_bool(0.6) {
-                    corner_list[rng.gen_range(0..corner_list.len())]
+                let corners = can_put & CORNERS;
+                if corners != 0 && rng.gen_bool(0.6) {
+                    nth_square(corners, rng.gen_range(0..bit_count(corners)))
                 } else if rng.gen_bool(0.6) {
-                    speedy_decide(&board, color)
+                    speedy_decide(&mut board, color, evaluator)
                 } else {
-                    moves[rng.gen_range(0..moves_len)]
+                    nth_square(can_put, rng.gen_range(0..moves_len))
                 }
             };
-            board.do_move(
-                Move::Mv {
-                    x_ah: m.0 as u32,
-                    y_18: m.1 as u32,
-                },
-                color,
-            );
-            color = color.opposite();
+            let flips = board.flips(square, color);
+            board.make(square, flips, color);
+            next = board.side_to_play(color.opposite());
         }
         let ret = board.win_or_lose(self.color);
         // self.wins += ret;
-        // self.mobility += self.canput_diff;
-        self.add(ret, self.canput_diff);
-        (ret, self.canput_diff)
+        // self.mobility += self.score;
+        self.add(ret, self.score);
+        (ret, self.score)
     }
 
     pub fn add(&mut self, wins: i32, mobility: i32) {
@@ -142,38 +131,38 @@ impl MonteNode {
         self.mobility += mobility;
     }
 
-    pub fn expand(&mut self) {
-        let moves = self.board.valid_moves(self.color);
+    pub fn expand(&mut self, evaluator: &dyn Evaluator) {
         self.is_expanded = true;
-        if moves.is_empty() {
-            if self.prev_is_skip {
+        match self.board.side_to_play(self.color) {
+            None => {
                 self.is_game_end = true;
                 return;
             }
-            let mut child = MonteNode::new(
-                self.board.clone(),
-                self.color.opposite(),
-                // Some(Box::new(self.clone())),
-                Some(Move::Pass),
-                self.stone_sum,
-            );
-            child.prev_is_skip = true;
-            self.children.push(child);
-            return;
+            Some(color) if color != self.color => {
+                self.children.push(MonteNode::new(
+                    self.board.clone(),
+                    self.color.opposite(),
+                    // Some(Box::new(self.clone())),
+                    Some(Move::Pass),
+                    self.stone_sum,
+                    evaluator,
+                ));
+                return;
+            }
+            Some(_) => {}
         }
-        for m in moves {
+        for square in Squares(self.board.can_put(self.color)) {
             let mut board = self.board.clone();
-            let selected_move = Move::Mv {
-                x_ah: m.0 as u32,
-                y_18: m.1 as u32,
-            };
-            board.do_move(selected_move, self.color);
+            let flips = board.flips(square, self.color);
+            board.make(square, flips, self.color);
+            let selected_move = Move::Mv(square);
             let child = MonteNode::new(
                 board,
                 self.color.opposite(),
                 // Some(Box::new(self.clone())),
                 Some(selected_move),
                 self.stone_sum + 1,
+                evaluator,
             );
             self.children.push(child);
         }
@@ -190,30 +179,33 @@ fn calculate_ucb(wins: i32, visits: i32, parent_visits: i32, mobility: i32, ston
         } else {
             0.0
         };
-        let ucb = win_rate - mobility_point * MOBILITY_SCALE
-            + 2.0 * (2.0 * (parent_visits as f64).ln() / visits as f64).sqrt();
-        ucb
+
+        win_rate - mobility_point * MOBILITY_SCALE
+            + 2.0 * (2.0 * (parent_visits as f64).ln() / visits as f64).sqrt()
     }
 }
 
-fn speedy_decide(board: &Board, color: Color) -> (usize, usize) {
-    let canput = board.valid_moves(color);
-    let mut max_score = -1000;
-    let mut max_score_index = 0;
-    for (i, pos) in canput.iter().enumerate() {
-        let mut board = board.clone();
-        let m = Move::Mv {
-            x_ah: pos.0 as u32,
-            y_18: pos.1 as u32,
-        };
-        board.do_move(m, color);
-        let score = board.canput_diff(color);
+fn nth_square(mask: u64, n: u64) -> Square {
+    Squares(mask)
+        .nth(n as usize)
+        .expect("n must be less than the number of bits")
+}
+
+/// 打った後の評価値が最も高い手
+fn speedy_decide(board: &mut Board, color: Color, evaluator: &dyn Evaluator) -> Square {
+    let mut max_score = i32::MIN;
+    let mut max_score_square = None;
+    for square in board.moves(color) {
+        let flips = board.flips(square, color);
+        board.make(square, flips, color);
+        let score = evaluator.evaluate(board, color);
+        board.unmake(square, flips, color);
         if score > max_score {
             max_score = score;
-            max_score_index = i;
+            max_score_square = Some(square);
         }
     }
-    canput[max_score_index]
+    max_score_square.expect("there must be a valid move")
 }
 
 fn calc_max_depth(root: &MonteNode) -> i32 {
@@ -227,21 +219,32 @@ fn calc_max_depth(root: &MonteNode) -> i32 {
     max_depth + 1
 }
 
-pub fn decide(board: &Board, color: Color, assigned_time_ms: i32) -> Move {
-    let mut root = MonteNode::new(board.clone(), color, None, board.sum_stones());
-    root.expand();
+/// 最も多く調べた手と探索の統計を返します。評価関数には着手可能数の差を使います。
+pub fn decide(board: &Board, color: Color, assigned_time_ms: i32) -> (Move, SearchStats) {
+    decide_with(board, color, assigned_time_ms, &Mobility)
+}
+
+/// [`decide`] と同じですが、プレイアウトの手の選び方と UCB に `evaluator` を使います。
+pub fn decide_with(
+    board: &Board,
+    color: Color,
+    assigned_time_ms: i32,
+    evaluator: &dyn Evaluator,
+) -> (Move, SearchStats) {
+    let mut root = MonteNode::new(board.clone(), color, None, board.sum_stones(), evaluator);
+    root.expand(evaluator);
     // let mut root = root;
     let start = std::time::Instant::now();
     while start.elapsed().as_millis() < assigned_time_ms as u128 {
-        root.play_out();
+        root.play_out(evaluator);
     }
     let mut max_visits = -1;
     let mut max_visits_index = 0;
     let mut sum_visits = 0;
     for (i, child) in root.children.iter().enumerate() {
         let winrate = child.wins as f64 / child.visits as f64;
-        print!(
-            "Move: {}, Max Depth:{}, n:{}, Winrate: {} tekazu: {}\n",
+        println!(
+            "Move: {}, Max Depth:{}, n:{}, Winrate: {} tekazu: {}",
             child.prev_move.unwrap(),
             calc_max_depth(child),
             child.visits,
@@ -258,8 +261,13 @@ pub fn decide(board: &Board, color: Color, assigned_time_ms: i32) -> Move {
             max_visits_index = i;
         }
     }
-    print!("Sum Visits: {}\n", sum_visits);
+    let stats = SearchStats {
+        nodes: sum_visits as u64,
+        elapsed: start.elapsed(),
+        depth: calc_max_depth(&root) as u32 - 1,
+        ..Default::default()
+    };
     let child = &root.children[max_visits_index];
-    let ret = child.prev_move.unwrap();
-    ret
+
+    (child.prev_move.unwrap(), stats)
 }
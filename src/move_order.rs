@@ -0,0 +1,179 @@
+//! 終盤探索の手の並べ方です。
+//!
+//! 良い手から読むほど alpha-beta の枝刈りが効きます。既定の [`MoveOrdering::FastestFirst`] は
+//! 置換表の最善手を最初に読み、残りは次の順で点数を付けて並べます。
+//!
+//! - 相手の着手可能数が少なくなる手 (速さ優先、fastest-first)
+//! - 隅に打つ手
+//! - 空きマスが奇数個の領域 (4x4 の象限) に打つ手 (偶数理論、最後に打てる側が得をする)
+
+use crate::bit_othello::{bit_count, Board, Flips, Squares, CORNERS};
+use crate::eval::Evaluator;
+use crate::proto::Color;
+use crate::square::Square;
+
+/// 盤面を 4x4 に分けた象限
+const QUADRANTS: [u64; 4] = [
+    0x000000000f0f0f0f,
+    0x00000000f0f0f0f0,
+    0x0f0f0f0f00000000,
+    0xf0f0f0f000000000,
+];
+
+/// 相手の着手可能数を数える空きマス数 (これより浅い局面は偶数理論と隅だけで並べる)
+const FASTEST_FIRST_EMPTIES: u64 = 7;
+
+const TT_MOVE_SCORE: i32 = 1 << 20;
+const MOBILITY_WEIGHT: i32 = 16;
+const CORNER_SCORE: i32 = 8;
+const PARITY_SCORE: i32 = 12;
+
+/// 手の並べ方
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MoveOrdering {
+    /// 並べ替えない (ビットの順)
+    None,
+    /// 打った後の評価値が高い順 (評価関数は [`ordered_moves`] に渡したもの)
+    Evaluation,
+    /// 速さ優先・隅・偶数理論
+    #[default]
+    FastestFirst,
+}
+
+/// 空きマスが奇数個の象限のマスクを返します。
+pub fn odd_regions(empty: u64) -> u64 {
+    QUADRANTS
+        .iter()
+        .filter(|&&quadrant| bit_count(empty & quadrant) % 2 == 1)
+        .fold(0, |mask, quadrant| mask | quadrant)
+}
+
+/// `can_put` の手を `(マス, 裏返る石)` にして、読む順に並べます。
+///
+/// `tt_move` があれば (`MoveOrdering::None` 以外では) 必ず最初になります。
+/// `evaluator` は `MoveOrdering::Evaluation` のときだけ使います。
+pub fn ordered_moves(
+    board: &mut Board,
+    color: Color,
+    can_put: u64,
+    tt_move: Option<Square>,
+    ordering: MoveOrdering,
+    evaluator: &dyn Evaluator,
+) -> Vec<(Square, Flips)> {
+    let mut moves: Vec<(Square, Flips)> = Squares(can_put)
+        .map(|square| (square, board.flips(square, color)))
+        .collect();
+    if moves.len() <= 1 {
+        return moves;
+    }
+    let tt_bonus = |square: Square| {
+        if Some(square) == tt_move {
+            TT_MOVE_SCORE
+        } else {
+            0
+        }
+    };
+    match ordering {
+        MoveOrdering::None => {}
+        MoveOrdering::Evaluation => {
+            moves.sort_by_cached_key(|&(square, flips)| {
+                board.make(square, flips, color);
+                let score = evaluator.evaluate(board, color);
+                board.unmake(square, flips, color);
+                std::cmp::Reverse(tt_bonus(square) + score)
+            });
+        }
+        MoveOrdering::FastestFirst => {
+            let empty = !(board.black | board.white);
+            let odd = odd_regions(empty);
+            let count_mobility = bit_count(empty) >= FASTEST_FIRST_EMPTIES;
+            moves.sort_by_cached_key(|&(square, flips)| {
+                let bit = square.bit();
+                let mut score = tt_bonus(square);
+                if bit & CORNERS != 0 {
+                    score += CORNER_SCORE;
+                }
+                if bit & odd != 0 {
+                    score += PARITY_SCORE;
+                }
+                if count_mobility {
+                    board.make(square, flips, color);
+                    let opponent = board.can_put(color.opposite());
+                    board.unmake(square, flips, color);
+                    score -= MOBILITY_WEIGHT * bit_count(opponent) as i32;
+                }
+                std::cmp::Reverse(score)
+            });
+        }
+    }
+    moves
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_othello::Position;
+    use crate::eval::Mobility;
+
+    fn sq(s: &str) -> Square {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_odd_regions() {
+        // 左上の象限だけ空きが1つ
+        let empty = sq("A1").bit() | sq("H8").bit() | sq("G8").bit();
+        assert_eq!(odd_regions(empty), QUADRANTS[0]);
+        assert_eq!(odd_regions(0), 0);
+    }
+
+    #[test]
+    fn test_tt_move_first() {
+        let mut board = Board::new();
+        let can_put = board.can_put(Color::Black);
+        for ordering in [MoveOrdering::Evaluation, MoveOrdering::FastestFirst] {
+            for square in Squares(can_put) {
+                let moves = ordered_moves(
+                    &mut board,
+                    Color::Black,
+                    can_put,
+                    Some(square),
+                    ordering,
+                    &Mobility,
+                );
+                assert_eq!(moves[0].0, square);
+                assert_eq!(moves.len(), 4);
+            }
+        }
+        assert_eq!(board, Board::new());
+    }
+
+    #[test]
+    fn test_fastest_first() {
+        // 黒は A1 (隅)・H3・F5 に打てる。A1 は相手の着手可能数が最も少なく、隅でもある
+        let position: Position = concat!(
+            "-OX-----",
+            "O-------",
+            "X-------",
+            "-------O",
+            "------OX",
+            "--------",
+            "--------",
+            "-------- X"
+        )
+        .parse()
+        .unwrap();
+        let mut board = position.board;
+        let can_put = board.can_put(Color::Black);
+        let moves = ordered_moves(
+            &mut board,
+            Color::Black,
+            can_put,
+            None,
+            MoveOrdering::FastestFirst,
+            &Mobility,
+        );
+        assert_eq!(moves.len(), 3);
+        assert_eq!(moves[0].0, sq("A1"));
+    }
+}
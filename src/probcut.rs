@@ -0,0 +1,362 @@
+//! 中盤探索の Multi-ProbCut です。
+//!
+//! 深さ `d` の探索値 `v_d` は浅い深さ `d'` の探索値から `v_d ≈ a * v_d' + b` (誤差の標準偏差 `sigma`)
+//! と推定できます。深さ `d` の null window の探索の前に深さ `d'` で読み、
+//! 推定値が `threshold * sigma` 以上の余裕で窓の外に出るなら、深く読まずに枝刈りします。
+//! 深さごと・局面の段階 ([`eval::phase`]) ごとに別の `a`・`b`・`sigma` を使います。
+//!
+//! パラメータは浅い探索値と深い探索値の組 ([`collect`]) から最小二乗法 ([`ProbCut::fit`]) で求め、
+//! テキストファイルに保存します。
+
+use crate::bit_othello::Board;
+use crate::eval::{self, Evaluator, NUM_PHASES};
+use crate::midgame::Searcher;
+use crate::proto::Color;
+use crate::transposition::TranspositionTable;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Instant;
+use thiserror::Error;
+
+/// 枝刈りを試す最小の深さ
+pub const MIN_DEPTH: u32 = 3;
+/// パラメータを持つ最大の深さ
+pub const MAX_DEPTH: u32 = 20;
+/// 既定の枝刈りの厳しさ (標準偏差の何倍の余裕があれば切るか)
+pub const DEFAULT_THRESHOLD: f64 = 1.5;
+/// パラメータを求めるのに必要な組の数
+const MIN_SAMPLES: usize = 10;
+/// パラメータファイルの先頭行
+const HEADER: &str = "probcut";
+/// パラメータファイルの形式の版
+pub const VERSION: u32 = 1;
+
+/// 深さ `depth` の探索の前に読む浅い深さ
+pub fn shallow_depth(depth: u32) -> u32 {
+    depth / 2
+}
+
+/// 1つの深さと段階の回帰パラメータ
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cut {
+    pub shallow_depth: u32,
+    pub a: f64,
+    pub b: f64,
+    pub sigma: f64,
+}
+
+/// 浅い探索値と深い探索値の組
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    pub phase: usize,
+    pub depth: u32,
+    pub shallow: i32,
+    pub deep: i32,
+}
+
+/// パラメータファイルを読めなかった理由
+#[derive(Debug, Error)]
+pub enum LoadProbCutError {
+    #[error("couldn't read probcut file: {0}")]
+    Io(#[from] io::Error),
+    #[error("unsupported probcut file version `{0}` (expected {VERSION})")]
+    UnsupportedVersion(String),
+    #[error("line {line}: {message}")]
+    Parse { line: usize, message: String },
+}
+
+/// 段階と深さごとの回帰パラメータ
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbCut {
+    pub threshold: f64,
+    cuts: Vec<Option<Cut>>,
+}
+
+impl ProbCut {
+    /// パラメータが1つもない (枝刈りしない) 表
+    pub fn new(threshold: f64) -> Self {
+        Self {
+            threshold,
+            cuts: vec![None; NUM_PHASES * (MAX_DEPTH as usize + 1)],
+        }
+    }
+
+    fn index(phase: usize, depth: u32) -> Option<usize> {
+        (phase < NUM_PHASES && depth <= MAX_DEPTH)
+            .then(|| phase * (MAX_DEPTH as usize + 1) + depth as usize)
+    }
+
+    pub fn get(&self, phase: usize, depth: u32) -> Option<&Cut> {
+        Self::index(phase, depth).and_then(|i| self.cuts[i].as_ref())
+    }
+
+    pub fn set(&mut self, phase: usize, depth: u32, cut: Cut) {
+        let i = Self::index(phase, depth).expect("phase and depth must be in range");
+        self.cuts[i] = Some(cut);
+    }
+
+    /// パラメータを持つ `(段階, 深さ, パラメータ)` を順に返します。
+    pub fn iter(&self) -> impl Iterator<Item = (usize, u32, &Cut)> {
+        let depths = MAX_DEPTH as usize + 1;
+        self.cuts.iter().enumerate().filter_map(move |(i, cut)| {
+            cut.as_ref()
+                .map(|cut| (i / depths, (i % depths) as u32, cut))
+        })
+    }
+
+    /// 探索値の組から段階と深さごとに最小二乗法でパラメータを求めます。
+    ///
+    /// 組が少ない (`MIN_SAMPLES` 未満の) 段階と深さにはパラメータを作りません。
+    pub fn fit(samples: &[Sample], threshold: f64) -> Self {
+        let mut probcut = Self::new(threshold);
+        for phase in 0..NUM_PHASES {
+            for depth in MIN_DEPTH..=MAX_DEPTH {
+                let pairs: Vec<(f64, f64)> = samples
+                    .iter()
+                    .filter(|s| s.phase == phase && s.depth == depth)
+                    .map(|s| (s.shallow as f64, s.deep as f64))
+                    .collect();
+                if pairs.len() < MIN_SAMPLES {
+                    continue;
+                }
+                let n = pairs.len() as f64;
+                let mean_x = pairs.iter().map(|p| p.0).sum::<f64>() / n;
+                let mean_y = pairs.iter().map(|p| p.1).sum::<f64>() / n;
+                let sxx: f64 = pairs.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+                let sxy: f64 = pairs.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+                // 浅い探索値が全て同じなら傾きは決まらないので、平均の差だけを使う
+                let a = if sxx > 0.0 { sxy / sxx } else { 1.0 };
+                let b = mean_y - a * mean_x;
+                let sse: f64 = pairs.iter().map(|p| (p.1 - a * p.0 - b).powi(2)).sum();
+                let sigma = (sse / (n - 2.0)).sqrt();
+                probcut.set(
+                    phase,
+                    depth,
+                    Cut {
+                        shallow_depth: shallow_depth(depth),
+                        a,
+                        b,
+                        sigma,
+                    },
+                );
+            }
+        }
+        probcut
+    }
+
+    /// テキスト形式に書き出します。
+    ///
+    /// 1行目は `probcut 版`、2行目は `threshold 値`、残りは1行に1つ
+    /// `段階 深さ 浅い深さ a b sigma` です。
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{HEADER} {VERSION}\nthreshold {}\n", self.threshold);
+        for (phase, depth, cut) in self.iter() {
+            writeln!(
+                text,
+                "{phase} {depth} {} {} {} {}",
+                cut.shallow_depth, cut.a, cut.b, cut.sigma
+            )
+            .expect("writing to a String never fails");
+        }
+        text
+    }
+
+    /// [`ProbCut::to_text`] の形式を読みます。空行と `#` から行末まではコメントとして読み飛ばします。
+    pub fn from_text(text: &str) -> Result<Self, LoadProbCutError> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.split('#').next().unwrap_or_default().trim()))
+            .filter(|(_, line)| !line.is_empty());
+        let parse_error = |line: usize, message: &str| LoadProbCutError::Parse {
+            line,
+            message: message.to_string(),
+        };
+
+        let (line, header) = lines.next().ok_or_else(|| parse_error(1, "empty file"))?;
+        match header.split_once(' ') {
+            Some((HEADER, version)) if version == VERSION.to_string() => {}
+            Some((HEADER, version)) => {
+                return Err(LoadProbCutError::UnsupportedVersion(version.to_string()))
+            }
+            _ => return Err(parse_error(line, "expected `probcut VERSION`")),
+        }
+        let (line, threshold) = lines
+            .next()
+            .ok_or_else(|| parse_error(line, "expected `threshold VALUE`"))?;
+        let threshold = threshold
+            .strip_prefix("threshold ")
+            .and_then(|t| t.trim().parse().ok())
+            .ok_or_else(|| parse_error(line, "expected `threshold VALUE`"))?;
+
+        let mut probcut = Self::new(threshold);
+        for (line, content) in lines {
+            let error = || parse_error(line, "expected `PHASE DEPTH SHALLOW A B SIGMA`");
+            let fields: Vec<&str> = content.split_whitespace().collect();
+            if fields.len() != 6 {
+                return Err(error());
+            }
+            let phase: usize = fields[0].parse().map_err(|_| error())?;
+            let depth: u32 = fields[1].parse().map_err(|_| error())?;
+            let shallow_depth: u32 = fields[2].parse().map_err(|_| error())?;
+            let mut values = [0.0; 3];
+            for (value, field) in values.iter_mut().zip(&fields[3..]) {
+                *value = field.parse().map_err(|_| error())?;
+            }
+            if Self::index(phase, depth).is_none() || shallow_depth >= depth {
+                return Err(parse_error(line, "phase or depth out of range"));
+            }
+            let [a, b, sigma] = values;
+            probcut.set(
+                phase,
+                depth,
+                Cut {
+                    shallow_depth,
+                    a,
+                    b,
+                    sigma,
+                },
+            );
+        }
+        Ok(probcut)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LoadProbCutError> {
+        Self::from_text(&fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_text())
+    }
+}
+
+/// `color` の手番の局面を `MIN_DEPTH` から `max_depth` までの深さと、それぞれの浅い深さで読み、
+/// 探索値の組を返します (終局までの手数より深い深さは読みません)。
+///
+/// どちらの値も、その深さだけを読んだときと同じ値です。
+pub fn collect(
+    board: &Board,
+    color: Color,
+    evaluator: &dyn Evaluator,
+    max_depth: u32,
+) -> Vec<Sample> {
+    let tt = TranspositionTable::new(1 << 16);
+    let mut searcher = Searcher::new(&tt, evaluator, Instant::now(), i32::MAX);
+    let limit = searcher.score_limit() + 1;
+    let empties = 64 - board.sum_stones() as u32;
+    let phase = eval::phase(board);
+    let mut board = board.clone();
+    let mut read = |depth| {
+        // 深く読んだ値が置換表に残っていると浅い探索でもそれを返すので、毎回空にする
+        tt.clear();
+        searcher
+            .search(&mut board, color, depth, -limit, limit)
+            .expect("no time limit")
+    };
+    (MIN_DEPTH..=max_depth.min(empties).min(MAX_DEPTH))
+        .map(|depth| Sample {
+            phase,
+            depth,
+            shallow: read(shallow_depth(depth)),
+            deep: read(depth),
+        })
+        .collect()
+}
+
+static PROBCUT: OnceLock<ProbCut> = OnceLock::new();
+
+/// エンジン全体で使うパラメータを設定します。最初の1回だけ有効で、設定できたら `true` を返します。
+pub fn install(probcut: ProbCut) -> bool {
+    PROBCUT.set(probcut).is_ok()
+}
+
+/// エンジン全体で使うパラメータ (設定されていなければ `None` で、枝刈りしません)
+pub fn installed() -> Option<&'static ProbCut> {
+    PROBCUT.get()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::Weights;
+
+    #[test]
+    fn test_fit() {
+        // deep = 2 * shallow + 3 に ±1 の誤差
+        let samples: Vec<Sample> = (0..100)
+            .map(|i| Sample {
+                phase: 4,
+                depth: 6,
+                shallow: i,
+                deep: 2 * i + 3 + if i % 2 == 0 { 1 } else { -1 },
+            })
+            .collect();
+        let probcut = ProbCut::fit(&samples, DEFAULT_THRESHOLD);
+        let cut = probcut.get(4, 6).unwrap();
+        assert_eq!(cut.shallow_depth, 3);
+        assert!((cut.a - 2.0).abs() < 0.01);
+        assert!((cut.b - 3.0).abs() < 0.5);
+        assert!((cut.sigma - 1.0).abs() < 0.05);
+        // 組がない段階と深さにはパラメータを作らない
+        assert_eq!(probcut.get(4, 7), None);
+        assert_eq!(probcut.iter().count(), 1);
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let mut probcut = ProbCut::new(1.2);
+        let cut = Cut {
+            shallow_depth: 2,
+            a: 0.95,
+            b: -12.5,
+            sigma: 210.25,
+        };
+        probcut.set(3, 5, cut);
+        probcut.set(NUM_PHASES - 1, MAX_DEPTH, cut);
+        assert_eq!(ProbCut::from_text(&probcut.to_text()).unwrap(), probcut);
+
+        assert!(matches!(
+            ProbCut::from_text("probcut 99\nthreshold 1\n"),
+            Err(LoadProbCutError::UnsupportedVersion(_))
+        ));
+        assert!(matches!(
+            ProbCut::from_text("probcut 1\nthreshold 1\n3 5 2 1.0\n"),
+            Err(LoadProbCutError::Parse { line: 3, .. })
+        ));
+        assert!(matches!(
+            ProbCut::from_text("probcut 1\nthreshold 1\n3 5 5 1 0 1\n"),
+            Err(LoadProbCutError::Parse { line: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn test_collect() {
+        let weights = Weights::fallback();
+        let board = Board::new();
+        let samples = collect(&board, Color::Black, &weights, 6);
+        let depths: Vec<u32> = samples.iter().map(|s| s.depth).collect();
+        assert_eq!(depths, vec![3, 4, 5, 6]);
+        assert!(samples.iter().all(|s| s.phase == 0));
+
+        // どちらの値も、その深さだけを読んだ値と同じ (前の深さの置換表の値を使わない)
+        let search = |depth| {
+            let tt = TranspositionTable::new(1 << 16);
+            let mut searcher = Searcher::new(&tt, &weights, Instant::now(), i32::MAX);
+            let limit = searcher.score_limit() + 1;
+            searcher
+                .search(&mut board.clone(), Color::Black, depth, -limit, limit)
+                .unwrap()
+        };
+        for sample in &samples {
+            assert_eq!(
+                sample.shallow,
+                search(shallow_depth(sample.depth)),
+                "{sample:?}"
+            );
+            assert_eq!(sample.deep, search(sample.depth), "{sample:?}");
+        }
+    }
+}
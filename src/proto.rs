@@ -1,9 +1,7 @@
-use std::{
-    char::from_u32,
-    fmt::{self, Display, Formatter},
-};
+use crate::square::Square;
+use std::fmt::{self, Display, Formatter};
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
 pub enum Color {
     None,
     White,
@@ -39,9 +37,9 @@ pub enum Wl {
     Tie,
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum Move {
-    Mv { x_ah: u32, y_18: u32 },
+    Mv(Square),
     Pass,
     GiveUp,
 }
@@ -51,11 +49,7 @@ impl Display for Move {
         match self {
             Self::Pass => write!(f, "PASS"),
             Self::GiveUp => write!(f, "GIVEUP"),
-            Self::Mv { x_ah, y_18 } => {
-                let cx = from_u32(x_ah + ('A' as u32) - 1).unwrap();
-                let cy = from_u32(y_18 + ('1' as u32) - 1).unwrap();
-                write!(f, "{cx}{cy}")
-            }
+            Self::Mv(square) => write!(f, "{square}"),
         }
     }
 }
@@ -132,7 +126,7 @@ mod tests {
             "OPEN Anon.\n".to_string()
         );
         assert_eq!(
-            Move(Mv { x_ah: 3, y_18: 4 }).to_string(),
+            Move(Mv("C4".parse().unwrap())).to_string(),
             "MOVE C4\n".to_string()
         );
     }
@@ -0,0 +1,106 @@
+use crate::transposition::TableStats;
+use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, Instant};
+
+/// 時間切れを確かめる間隔 (ノード数)
+const TIME_CHECK_INTERVAL: u64 = 1024;
+
+/// 探索の統計です。どの探索 (モンテカルロ木探索、読み切り) も結果と一緒に返します。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchStats {
+    /// 探索したノード数 (モンテカルロ木探索ではプレイアウトの回数)
+    pub nodes: u64,
+    pub elapsed: Duration,
+    /// 読んだ深さ (読み切りでは空きマス数、モンテカルロ木探索では木の深さ)
+    pub depth: u32,
+    /// 置換表を引いた回数とヒットした回数
+    pub tt: TableStats,
+    /// 時間切れで探索を打ち切ったかどうか
+    pub timed_out: bool,
+}
+
+impl SearchStats {
+    pub fn nodes_per_second(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs == 0.0 {
+            0.0
+        } else {
+            self.nodes as f64 / secs
+        }
+    }
+
+    /// 続けて行った探索の統計を足し合わせます。
+    pub fn merge(&mut self, other: &Self) {
+        self.nodes += other.nodes;
+        self.elapsed += other.elapsed;
+        self.depth = self.depth.max(other.depth);
+        self.tt.probes += other.tt.probes;
+        self.tt.hits += other.tt.hits;
+        self.tt.stores += other.tt.stores;
+        self.timed_out |= other.timed_out;
+    }
+}
+
+impl Display for SearchStats {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "nodes: {}, time: {:.3}s, nps: {:.0}, depth: {}",
+            self.nodes,
+            self.elapsed.as_secs_f64(),
+            self.nodes_per_second(),
+            self.depth
+        )?;
+        if self.tt.probes > 0 {
+            write!(f, ", tt hit: {:.1}%", self.tt.hit_rate() * 100.0)?;
+        }
+        if self.timed_out {
+            write!(f, " (timed out)")?;
+        }
+        Ok(())
+    }
+}
+
+/// 探索器が数えるノード数と置換表の統計、時間切れの判定です。読み切りと中盤の探索で使います。
+#[derive(Debug, Clone)]
+pub(crate) struct SearchCounter {
+    start_time: Instant,
+    assigned_time_ms: i32,
+    pub(crate) nodes: u64,
+    /// 次に時間切れを確かめるノード数
+    next_check: u64,
+    timed_out: bool,
+    pub(crate) tt: TableStats,
+}
+
+impl SearchCounter {
+    pub(crate) fn new(start_time: Instant, assigned_time_ms: i32) -> Self {
+        Self {
+            start_time,
+            assigned_time_ms,
+            nodes: 0,
+            next_check: TIME_CHECK_INTERVAL,
+            timed_out: false,
+            tt: TableStats::default(),
+        }
+    }
+
+    /// 時間切れなら `true` を返します。時計を見るのは一定のノード数ごとです。
+    pub(crate) fn check_timeout(&mut self) -> bool {
+        if !self.timed_out && self.nodes >= self.next_check {
+            self.next_check = self.nodes + TIME_CHECK_INTERVAL;
+            self.timed_out = self.start_time.elapsed().as_millis() > self.assigned_time_ms as u128;
+        }
+        self.timed_out
+    }
+
+    /// ここまでの統計 (経過時間と深さは呼び出し側で埋めます)
+    pub(crate) fn stats(&self) -> SearchStats {
+        SearchStats {
+            nodes: self.nodes,
+            tt: self.tt,
+            timed_out: self.timed_out,
+            ..Default::default()
+        }
+    }
+}
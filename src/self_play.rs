@@ -0,0 +1,159 @@
+//! 2つの設定 ([`Config`]) で対局させて強さを比べます。
+//!
+//! 同じ開始局面を先手・後手を入れ替えて2局ずつ打つので、開始局面の有利不利は打ち消し合います。
+
+use crate::ai_decide::{self, Config};
+use crate::game::Game;
+use crate::proto::{Color, Move};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+/// `first` から見た対戦成績
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MatchResult {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+    /// 石差の合計
+    pub disc_diff: i64,
+}
+
+impl MatchResult {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    /// 引き分けを半分の勝ちとした勝率
+    pub fn score(&self) -> f64 {
+        if self.games() == 0 {
+            0.0
+        } else {
+            (self.wins as f64 + self.draws as f64 / 2.0) / self.games() as f64
+        }
+    }
+
+    /// 1局の結果 (`first` から見た石差) を加えます。
+    pub fn add(&mut self, diff: i32) {
+        match diff.signum() {
+            1 => self.wins += 1,
+            0 => self.draws += 1,
+            _ => self.losses += 1,
+        }
+        self.disc_diff += diff as i64;
+    }
+}
+
+impl Display for MatchResult {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}-{} (score: {:.1}%, average diff: {:+.1})",
+            self.wins,
+            self.draws,
+            self.losses,
+            self.score() * 100.0,
+            if self.games() == 0 {
+                0.0
+            } else {
+                self.disc_diff as f64 / self.games() as f64
+            }
+        )
+    }
+}
+
+/// 初期局面からランダムに `plies` 手打った棋譜を返します (開始局面を散らすため)。
+pub fn random_opening(rng: &mut impl Rng, plies: usize) -> Vec<Move> {
+    let mut game = Game::new();
+    while game.kihu().len() < plies && !game.is_over() {
+        if game.pass_if_needed() {
+            continue;
+        }
+        let moves = game.board().valid_moves(game.side_to_move());
+        let square = moves[rng.gen_range(0..moves.len())];
+        game.play(Move::Mv(square))
+            .expect("random opening must be legal");
+    }
+    game.kihu().to_vec()
+}
+
+/// `opening` の後を黒 `black`、白 `white` で終局まで打ち、終局した対局を返します。
+pub fn play_game(
+    opening: &[Move],
+    black: &Config,
+    white: &Config,
+    book_dict: &HashMap<String, String>,
+) -> Game {
+    let mut game = Game::new();
+    for &m in opening {
+        game.play(m).expect("opening must be legal");
+    }
+    while !game.is_over() {
+        let config = if game.side_to_move() == Color::Black {
+            black
+        } else {
+            white
+        };
+        let (m, _) = ai_decide::decide_with(&game, book_dict, config);
+        game.play(m).expect("engine must play a legal move");
+    }
+    game
+}
+
+/// 終局した対局の黒から見た石差
+fn final_score(game: &Game) -> i32 {
+    game.final_score().expect("game must be over")
+}
+
+/// `first` と `second` で `pairs` 組 (各組は先後を入れ替えた2局) 打ち、`first` から見た成績を返します。
+///
+/// 開始局面は `seed` から決まるランダムな `opening_plies` 手です。1局ごとにそこまでの成績と終局した対局で `on_game` を呼びます。
+pub fn run_match(
+    first: &Config,
+    second: &Config,
+    pairs: u32,
+    opening_plies: usize,
+    seed: u64,
+    book_dict: &HashMap<String, String>,
+    mut on_game: impl FnMut(&MatchResult, &Game),
+) -> MatchResult {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut result = MatchResult::default();
+    for _ in 0..pairs {
+        let opening = random_opening(&mut rng, opening_plies);
+        let game = play_game(&opening, first, second, book_dict);
+        result.add(final_score(&game));
+        on_game(&result, &game);
+        let game = play_game(&opening, second, first, book_dict);
+        result.add(-final_score(&game));
+        on_game(&result, &game);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_opening() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let opening = random_opening(&mut rng, 6);
+        assert_eq!(opening.len(), 6);
+        // 同じ種なら同じ開始局面
+        let mut rng = StdRng::seed_from_u64(1);
+        assert_eq!(random_opening(&mut rng, 6), opening);
+    }
+
+    #[test]
+    fn test_match_result() {
+        let mut result = MatchResult::default();
+        for diff in [10, 0, -4, 2] {
+            result.add(diff);
+        }
+        assert_eq!((result.wins, result.draws, result.losses), (2, 1, 1));
+        assert_eq!(result.disc_diff, 8);
+        assert_eq!(result.score(), 0.625);
+    }
+}
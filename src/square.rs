@@ -0,0 +1,152 @@
+use crate::bit_othello::Pos;
+use crate::symmetry::Symmetry;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use thiserror::Error;
+
+/// 盤面のマス
+///
+/// 中身はビットボードのビット番号 `(x - 1) * 8 + (y - 1)` で、
+/// 1始まりの座標 `Pos` や `"C4"` のような文字列と相互に変換できます。
+///
+/// ```
+/// use fl_reversi_rs::square::Square;
+///
+/// let c4: Square = "c4".parse().unwrap();
+/// assert_eq!(c4.to_pos(), (3, 4));
+/// assert_eq!(c4.index(), 19);
+/// assert_eq!(c4.to_string(), "C4");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Square(u8);
+
+#[derive(Debug, Error, PartialEq, Clone)]
+#[error("invalid square `{0}` (expected A1-H8)")]
+pub struct ParseSquareError(pub String);
+
+impl Square {
+    /// ビット番号からマスを作ります。
+    pub const fn from_index(index: usize) -> Option<Self> {
+        if index < 64 {
+            Some(Self(index as u8))
+        } else {
+            None
+        }
+    }
+
+    /// 範囲を確かめずにビット番号からマスを作ります。`index` は64未満でなければいけません。
+    pub(crate) const fn from_index_unchecked(index: usize) -> Self {
+        debug_assert!(index < 64);
+        Self(index as u8)
+    }
+
+    /// 1始まりの座標 `(x, y)` からマスを作ります。
+    pub fn from_pos(pos: Pos) -> Option<Self> {
+        let (x, y) = pos;
+        if (1..=8).contains(&x) && (1..=8).contains(&y) {
+            Some(Self(((x - 1) * 8 + y - 1) as u8))
+        } else {
+            None
+        }
+    }
+
+    pub const fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    pub const fn bit(self) -> u64 {
+        1 << self.0
+    }
+
+    /// 1始まりの座標 `(x, y)`
+    pub const fn to_pos(self) -> Pos {
+        (self.x() as usize, self.y() as usize)
+    }
+
+    /// A-H を 1-8 で表した列
+    pub const fn x(self) -> u32 {
+        (self.0 / 8 + 1) as u32
+    }
+
+    /// 1-8 の行
+    pub const fn y(self) -> u32 {
+        (self.0 % 8 + 1) as u32
+    }
+
+    /// 対称変換したマスを返します。
+    pub fn transform(self, sym: Symmetry) -> Self {
+        Self::from_pos(sym.apply_pos(self.to_pos())).expect("symmetry must stay on the board")
+    }
+}
+
+impl FromStr for Square {
+    type Err = ParseSquareError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseSquareError(s.to_string());
+        let mut chars = s.chars();
+        let (x, y) = match (chars.next(), chars.next(), chars.next()) {
+            (Some(x), Some(y), None) => (x.to_ascii_uppercase(), y),
+            _ => return Err(err()),
+        };
+        if !matches!(x, 'A'..='H') || !matches!(y, '1'..='8') {
+            return Err(err());
+        }
+        let pos = (
+            (x as u32 - 'A' as u32 + 1) as usize,
+            (y as u32 - '1' as u32 + 1) as usize,
+        );
+        Self::from_pos(pos).ok_or_else(err)
+    }
+}
+
+impl Display for Square {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let cx = (b'A' + self.0 / 8) as char;
+        let cy = (b'1' + self.0 % 8) as char;
+        write!(f, "{cx}{cy}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_display() {
+        for index in 0..64 {
+            let square = Square::from_index(index).unwrap();
+            let s = square.to_string();
+            assert_eq!(s.parse::<Square>(), Ok(square));
+            assert_eq!(s.to_lowercase().parse::<Square>(), Ok(square));
+            assert_eq!(Square::from_pos(square.to_pos()), Some(square));
+        }
+        assert_eq!("A1".parse::<Square>().unwrap().index(), 0);
+        assert_eq!("H8".parse::<Square>().unwrap().index(), 63);
+        for s in ["", "A", "A0", "I1", "A9", "C44", "4C"] {
+            assert_eq!(s.parse::<Square>(), Err(ParseSquareError(s.to_string())));
+        }
+        assert_eq!(Square::from_index(64), None);
+        assert_eq!(Square::from_pos((0, 1)), None);
+    }
+
+    #[test]
+    fn test_transform() {
+        let c4: Square = "C4".parse().unwrap();
+        let expected = [
+            (Symmetry::Identity, "C4"),
+            (Symmetry::Rotate90, "E3"),
+            (Symmetry::Rotate180, "F5"),
+            (Symmetry::Rotate270, "D6"),
+            (Symmetry::FlipX, "F4"),
+            (Symmetry::FlipY, "C5"),
+            (Symmetry::Transpose, "D3"),
+            (Symmetry::AntiTranspose, "E6"),
+        ];
+        for (sym, s) in expected {
+            assert_eq!(c4.transform(sym).to_string(), s, "{sym:?}");
+            assert_eq!(c4.transform(sym).transform(sym.inverse()), c4);
+            assert_eq!(sym.apply_bits(c4.bit()), c4.transform(sym).bit());
+        }
+    }
+}
@@ -0,0 +1,182 @@
+//! 確定石 (以後どう打っても裏返らない石) の計算です。
+//!
+//! 縦・横・2つの斜めの4方向それぞれで、次のどれかを満たす石を確定石とします。
+//!
+//! - その方向の列が全て埋まっている
+//! - その方向の両隣のどちらかが盤の外か、同じ色の確定石
+//!
+//! 隅から辺、辺から内側へと広がるように、変わらなくなるまで繰り返します。
+//! 全ての確定石を見つけられるわけではないので、確定石の数の下界になります。
+
+use crate::bit_othello::Board;
+use crate::proto::Color;
+
+/// 縦 (列の中の隣、ビットを1つずらす) 方向で盤の外に接するマス (1行目と8行目)
+const WALL_VERTICAL: u64 = 0x8181818181818181;
+/// 横 (列を1つずらす) 方向で盤の外に接するマス (A列とH列)
+const WALL_HORIZONTAL: u64 = 0xff000000000000ff;
+/// 盤の縁のマス
+const BORDER: u64 = 0xff818181818181ff;
+/// 各列の1行目を除いたマス (行が1つ増える向きにずらした後の回り込みを消す)
+const NOT_ROW_1: u64 = 0xfefefefefefefefe;
+/// 各列の8行目を除いたマス
+const NOT_ROW_8: u64 = 0x7f7f7f7f7f7f7f7f;
+
+/// 斜めの列のマスク (`[0]` は列が1つ増えると行が1つ減る向き、`[1]` は行も1つ増える向き)
+const DIAGONALS: [[u64; 15]; 2] = {
+    let mut diagonals = [[0; 15]; 2];
+    let mut x = 0;
+    while x < 8 {
+        let mut y = 0;
+        while y < 8 {
+            let bit = 1u64 << (x * 8 + y);
+            diagonals[0][x + y] |= bit;
+            diagonals[1][x + 7 - y] |= bit;
+            y += 1;
+        }
+        x += 1;
+    }
+    diagonals
+};
+
+/// 4方向それぞれについて、その方向の列が全て埋まっているマス
+fn full_lines(occupied: u64) -> [u64; 4] {
+    // 縦: 1列 (1バイト) が全て埋まっている
+    let mut vertical = 0;
+    for i in 0..8 {
+        if (occupied >> (i * 8)) & 0xff == 0xff {
+            vertical |= 0xff << (i * 8);
+        }
+    }
+    // 横: 同じ行のビットが全ての列で立っている
+    let mut row = 0xff;
+    for i in 0..8 {
+        row &= occupied >> (i * 8);
+    }
+    let horizontal = (row & 0xff) * 0x0101010101010101;
+
+    let mut diagonal = [0; 2];
+    for (full, masks) in diagonal.iter_mut().zip(DIAGONALS.iter()) {
+        for &mask in masks {
+            if occupied & mask == mask {
+                *full |= mask;
+            }
+        }
+    }
+    [vertical, horizontal, diagonal[0], diagonal[1]]
+}
+
+/// `player` の石のうち確定石のマスク
+fn stable_of(player: u64, full: &[u64; 4]) -> u64 {
+    let [full_vertical, full_horizontal, full_up, full_down] = *full;
+    let mut stable = 0;
+    loop {
+        let vertical = full_vertical
+            | WALL_VERTICAL
+            | ((stable << 1) & NOT_ROW_1)
+            | ((stable >> 1) & NOT_ROW_8);
+        let horizontal = full_horizontal | WALL_HORIZONTAL | (stable << 8) | (stable >> 8);
+        // 列が1つ増えると行が1つ減る向き
+        let up = full_up | BORDER | ((stable << 7) & NOT_ROW_8) | ((stable >> 7) & NOT_ROW_1);
+        // 列が1つ増えると行も1つ増える向き
+        let down = full_down | BORDER | ((stable << 9) & NOT_ROW_1) | ((stable >> 9) & NOT_ROW_8);
+        let next = player & vertical & horizontal & up & down;
+        if next == stable {
+            return stable;
+        }
+        stable = next;
+    }
+}
+
+impl Board {
+    /// 黒と白の確定石のマスク `(black, white)` を返します (確定石の下界)。
+    pub fn stable_discs(&self) -> (u64, u64) {
+        let full = full_lines(self.black | self.white);
+        (stable_of(self.black, &full), stable_of(self.white, &full))
+    }
+
+    /// `color` の確定石のマスク
+    pub fn stable_discs_of(&self, color: Color) -> u64 {
+        let full = full_lines(self.black | self.white);
+        if color == Color::Black {
+            stable_of(self.black, &full)
+        } else {
+            stable_of(self.white, &full)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_othello::{bit_count, Position};
+    use crate::proto::Move;
+    use rand::Rng;
+
+    #[test]
+    fn test_diagonals() {
+        for masks in DIAGONALS {
+            assert_eq!(masks.iter().fold(0, |acc, m| acc | m), u64::MAX);
+            assert_eq!(masks.iter().map(|&m| bit_count(m)).sum::<u64>(), 64);
+        }
+    }
+
+    #[test]
+    fn test_stable_discs() {
+        assert_eq!(Board::new().stable_discs(), (0, 0));
+
+        // 隅とそこから辺に続く石は確定石
+        let position: Position = concat!(
+            "XXXO----",
+            "X-------",
+            "--------",
+            "--------",
+            "--------",
+            "--------",
+            "--------",
+            "-------- O"
+        )
+        .parse()
+        .unwrap();
+        let (black, white) = position.board.stable_discs();
+        let sq = |s: &str| s.parse::<crate::square::Square>().unwrap().bit();
+        assert_eq!(black, sq("A1") | sq("B1") | sq("C1") | sq("A2"));
+        assert_eq!(white, 0);
+
+        // 全て埋まった盤面は全て確定石
+        let position: Position = format!("{} X", "XO".repeat(32)).parse().unwrap();
+        let (black, white) = position.board.stable_discs();
+        assert_eq!(black | white, u64::MAX);
+    }
+
+    #[test]
+    fn test_stable_discs_never_flip() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..200 {
+            let mut board = Board::new();
+            let mut color = Color::Black;
+            let mut passed = false;
+            let mut stable = (0, 0);
+            loop {
+                let moves = board.valid_moves(color);
+                if moves.is_empty() {
+                    if passed {
+                        break;
+                    }
+                    passed = true;
+                } else {
+                    passed = false;
+                    board.do_move(Move::Mv(moves[rng.gen_range(0..moves.len())]), color);
+                }
+                color = color.opposite();
+                // 一度確定石になった石は、その後も同じ色のまま
+                assert_eq!(board.black & stable.0, stable.0);
+                assert_eq!(board.white & stable.1, stable.1);
+                let (black, white) = board.stable_discs();
+                assert_eq!(black & stable.0, stable.0);
+                assert_eq!(white & stable.1, stable.1);
+                stable = (black, white);
+            }
+        }
+    }
+}
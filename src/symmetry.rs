@@ -0,0 +1,178 @@
+use crate::bit_othello::{Board, Pos};
+
+/// 盤面の8通りの対称変換 (回転と鏡映)
+///
+/// 座標は `(x, y)` (x: A-H, y: 1-8) で、回転は盤面を表示したときの時計回りです。
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Symmetry {
+    Identity,
+    /// (x, y) -> (9 - y, x)
+    Rotate90,
+    /// (x, y) -> (9 - x, 9 - y)
+    Rotate180,
+    /// (x, y) -> (y, 9 - x)
+    Rotate270,
+    /// (x, y) -> (9 - x, y)
+    FlipX,
+    /// (x, y) -> (x, 9 - y)
+    FlipY,
+    /// (x, y) -> (y, x)
+    Transpose,
+    /// (x, y) -> (9 - y, 9 - x)
+    AntiTranspose,
+}
+
+impl Symmetry {
+    pub const ALL: [Symmetry; 8] = [
+        Symmetry::Identity,
+        Symmetry::Rotate90,
+        Symmetry::Rotate180,
+        Symmetry::Rotate270,
+        Symmetry::FlipX,
+        Symmetry::FlipY,
+        Symmetry::Transpose,
+        Symmetry::AntiTranspose,
+    ];
+
+    /// 逆変換を返します。
+    pub fn inverse(self) -> Self {
+        match self {
+            Self::Rotate90 => Self::Rotate270,
+            Self::Rotate270 => Self::Rotate90,
+            _ => self,
+        }
+    }
+
+    /// 1始まりの座標を変換します。
+    pub fn apply_pos(self, pos: Pos) -> Pos {
+        let (x, y) = pos;
+        match self {
+            Self::Identity => (x, y),
+            Self::Rotate90 => (9 - y, x),
+            Self::Rotate180 => (9 - x, 9 - y),
+            Self::Rotate270 => (y, 9 - x),
+            Self::FlipX => (9 - x, y),
+            Self::FlipY => (x, 9 - y),
+            Self::Transpose => (y, x),
+            Self::AntiTranspose => (9 - y, 9 - x),
+        }
+    }
+
+    /// ビットボードを変換します。
+    pub fn apply_bits(self, bits: u64) -> u64 {
+        match self {
+            Self::Identity => bits,
+            Self::Rotate90 => flip_x(transpose(bits)),
+            Self::Rotate180 => flip_x(flip_y(bits)),
+            Self::Rotate270 => flip_y(transpose(bits)),
+            Self::FlipX => flip_x(bits),
+            Self::FlipY => flip_y(bits),
+            Self::Transpose => transpose(bits),
+            Self::AntiTranspose => flip_x(flip_y(transpose(bits))),
+        }
+    }
+}
+
+/// A-H を左右反転します。ビットボードは1バイトが1列なのでバイトを逆順にするだけです。
+pub fn flip_x(bits: u64) -> u64 {
+    bits.swap_bytes()
+}
+
+/// 1-8 を上下反転します (各バイト内のビットを逆順にする)。
+pub fn flip_y(bits: u64) -> u64 {
+    let mut x = bits;
+    x = ((x >> 1) & 0x5555555555555555) | ((x & 0x5555555555555555) << 1);
+    x = ((x >> 2) & 0x3333333333333333) | ((x & 0x3333333333333333) << 2);
+    x = ((x >> 4) & 0x0f0f0f0f0f0f0f0f) | ((x & 0x0f0f0f0f0f0f0f0f) << 4);
+    x
+}
+
+/// A1-H8 の対角線で転置します。
+pub fn transpose(bits: u64) -> u64 {
+    let mut x = bits;
+    let mut t = 0x0f0f0f0f00000000 & (x ^ (x << 28));
+    x ^= t ^ (t >> 28);
+    t = 0x3333000033330000 & (x ^ (x << 14));
+    x ^= t ^ (t >> 14);
+    t = 0x5500550055005500 & (x ^ (x << 7));
+    x ^= t ^ (t >> 7);
+    x
+}
+
+impl Board {
+    /// 対称変換した盤面を返します。
+    pub fn transform(&self, sym: Symmetry) -> Board {
+        Board {
+            black: sym.apply_bits(self.black),
+            white: sym.apply_bits(self.white),
+        }
+    }
+
+    /// 8通りの対称な盤面のうち `(black, white)` が最小のものと、
+    /// そこへ移す変換を返します。対称な局面はすべて同じ代表になります。
+    pub fn canonical(&self) -> (Board, Symmetry) {
+        let mut best = (self.clone(), Symmetry::Identity);
+        for sym in Symmetry::ALL.into_iter().skip(1) {
+            let board = self.transform(sym);
+            if (board.black, board.white) < (best.0.black, best.0.white) {
+                best = (board, sym);
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::Color;
+    use crate::square::Square;
+    use rand::Rng;
+
+    fn naive(sym: Symmetry, bits: u64) -> u64 {
+        let mut ret = 0;
+        for index in 0..64 {
+            let square = Square::from_index(index).unwrap();
+            if bits & square.bit() != 0 {
+                let pos = sym.apply_pos(square.to_pos());
+                ret |= Square::from_pos(pos).unwrap().bit();
+            }
+        }
+        ret
+    }
+
+    #[test]
+    fn test_apply_bits_matches_pos() {
+        let mut rng = rand::thread_rng();
+        for sym in Symmetry::ALL {
+            for _ in 0..100 {
+                let bits: u64 = rng.gen();
+                assert_eq!(sym.apply_bits(bits), naive(sym, bits), "{sym:?}");
+                assert_eq!(sym.inverse().apply_bits(sym.apply_bits(bits)), bits);
+            }
+        }
+    }
+
+    #[test]
+    fn test_canonical() {
+        // C4 と対称な初手はすべて同じ代表になる
+        let boards: Vec<Board> = Board::new()
+            .moves(Color::Black)
+            .map(|square| {
+                let mut board = Board::new();
+                let flips = board.flips(square, Color::Black);
+                board.make(square, flips, Color::Black);
+                board
+            })
+            .collect();
+        let (canonical, _) = boards[0].canonical();
+        for board in &boards {
+            let (c, sym) = board.canonical();
+            assert_eq!(c, canonical);
+            let t = board.transform(sym);
+            assert_eq!(t, c);
+            // 変換しても合法手の数は変わらない
+            assert_eq!(t.valid_moves(Color::White).len(), 3);
+        }
+    }
+}
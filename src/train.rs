@@ -0,0 +1,398 @@
+//! 評価関数の重みの学習です。
+//!
+//! 局面と、その局面から打ち進めた最終的な石差の組を集め、
+//! 評価値が石差 × [`SCALE`] に近くなるように段階ごとに重みを求めます。
+//!
+//! パターンの特徴は [`eval::extract`] で取り出します。対称変換した並びは同じ重みの表を使うので
+//! (symmetry folding)、1つの局面の4辺や4隅の並びが同じ重みの学習に使われます。
+//!
+//! 学習は L2 正則化付きの最小二乗法を、重みごとに正規化した勾配法 (各重みの出現回数で割る) で解きます。
+//! 正則化は学習前の重みに引き寄せるので、データにない並びの重みは元の値のまま残ります。
+
+use crate::bit_othello::{Board, ParsePositionError, Position};
+use crate::eval::{self, Features, Weights, NUM_PHASES, SCALE};
+use crate::game::Game;
+use crate::proto::{Color, Move};
+use crate::square::{ParseSquareError, Square};
+use rayon::prelude::*;
+use std::io::{self, BufRead};
+use thiserror::Error;
+
+/// 学習に使う1局面
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sample {
+    pub features: Features,
+    /// 手番側から見た最終的な石差
+    pub target: i32,
+}
+
+impl Sample {
+    /// `color` の手番の `board` から、`color` から見て石差 `target` で終局した局面
+    pub fn new(board: &Board, color: Color, target: i32) -> Self {
+        Self {
+            features: eval::extract(board, color),
+            target,
+        }
+    }
+}
+
+/// 学習データを読めなかった理由
+#[derive(Debug, Error)]
+pub enum ReadSamplesError {
+    #[error("couldn't read training data: {0}")]
+    Io(#[from] io::Error),
+    #[error("line {line}: {source}")]
+    Position {
+        line: usize,
+        source: ParsePositionError,
+    },
+    #[error("line {line}: {source}")]
+    Square {
+        line: usize,
+        source: ParseSquareError,
+    },
+    #[error("line {line}: illegal move {mv}")]
+    IllegalMove { line: usize, mv: Move },
+    #[error("line {line}: {message}")]
+    Parse { line: usize, message: String },
+}
+
+/// 終局した対局の棋譜を、学習データの1行の形 (`棋譜 石差`) にします。
+///
+/// 棋譜はパスを除いた手を `C4C3D3` のようにつなげたもので、石差は黒から見た値です。
+pub fn format_record(game: &Game) -> String {
+    let moves: String = game
+        .kihu()
+        .iter()
+        .filter_map(|m| match m {
+            Move::Mv(square) => Some(square.to_string()),
+            _ => None,
+        })
+        .collect();
+    let score = game
+        .final_score()
+        .unwrap_or_else(|| game.board().diff_stones(Color::Black));
+    format!("{moves} {score:+}")
+}
+
+/// 棋譜 `record` (パスを除いた手をつなげたもの) の各局面を、黒から見た最終石差 `score` で学習データにします。
+///
+/// 棋譜が終局まであれば `score` は省略でき、書いた場合は終局の石差と一致しなければなりません。
+fn samples_from_record(
+    line: usize,
+    record: &str,
+    score: Option<i32>,
+) -> Result<Vec<Sample>, ReadSamplesError> {
+    if !record.is_ascii() || !record.len().is_multiple_of(2) {
+        return Err(ReadSamplesError::Parse {
+            line,
+            message: "a game record must be a list of squares like `F5D6C3`".to_string(),
+        });
+    }
+    let mut game = Game::new();
+    let mut positions = vec![];
+    for i in (0..record.len()).step_by(2) {
+        let square: Square = record[i..i + 2]
+            .parse()
+            .map_err(|source| ReadSamplesError::Square { line, source })?;
+        game.pass_if_needed();
+        positions.push((game.board().clone(), game.side_to_move()));
+        let mv = Move::Mv(square);
+        game.play(mv)
+            .map_err(|_| ReadSamplesError::IllegalMove { line, mv })?;
+    }
+    let score = match (game.final_score(), score) {
+        (Some(final_score), Some(score)) if final_score != score => {
+            return Err(ReadSamplesError::Parse {
+                line,
+                message: format!(
+                    "score {score:+} doesn't match the final position ({final_score:+})"
+                ),
+            })
+        }
+        (Some(score), _) | (None, Some(score)) => score,
+        (None, None) => {
+            return Err(ReadSamplesError::Parse {
+                line,
+                message: "an unfinished game record needs the final score".to_string(),
+            })
+        }
+    };
+    Ok(positions
+        .iter()
+        .map(|(board, color)| {
+            let target = if *color == Color::Black {
+                score
+            } else {
+                -score
+            };
+            Sample::new(board, *color, target)
+        })
+        .collect())
+}
+
+/// 学習データを読みます。
+///
+/// 1行に1つ、次のどちらかを書きます。`#` から行末まではコメントです。
+///
+/// - `局面 手番 石差`: `solve` と同じ形式の局面と、手番側から見た最終石差
+/// - `棋譜 [石差]`: [`format_record`] の形式の棋譜で、途中の全ての局面を使います
+pub fn read_samples(reader: impl BufRead) -> Result<Vec<Sample>, ReadSamplesError> {
+    let mut samples = vec![];
+    for (i, line) in reader.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line?;
+        let content = line.split('#').next().unwrap_or_default();
+        let fields: Vec<&str> = content.split_whitespace().collect();
+        let parse_score = |s: &str| {
+            s.parse::<i32>().map_err(|e| ReadSamplesError::Parse {
+                line: line_number,
+                message: format!("invalid score `{s}`: {e}"),
+            })
+        };
+        match fields[..] {
+            [] => {}
+            [record] => samples.extend(samples_from_record(line_number, record, None)?),
+            [record, score] => samples.extend(samples_from_record(
+                line_number,
+                record,
+                Some(parse_score(score)?),
+            )?),
+            [board, side, score] => {
+                let position: Position = format!("{board} {side}").parse().map_err(|source| {
+                    ReadSamplesError::Position {
+                        line: line_number,
+                        source,
+                    }
+                })?;
+                samples.push(Sample::new(
+                    &position.board,
+                    position.color,
+                    parse_score(score)?,
+                ));
+            }
+            _ => {
+                return Err(ReadSamplesError::Parse {
+                    line: line_number,
+                    message: "expected `POSITION SIDE SCORE` or `MOVES [SCORE]`".to_string(),
+                })
+            }
+        }
+    }
+    Ok(samples)
+}
+
+/// 学習の設定
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrainConfig {
+    /// 全データを使って重みを更新する回数
+    pub epochs: usize,
+    /// L2 正則化の強さ (学習前の重みからずれるほど損をします)
+    pub lambda: f64,
+}
+
+impl Default for TrainConfig {
+    fn default() -> Self {
+        Self {
+            epochs: 100,
+            lambda: 1.0,
+        }
+    }
+}
+
+/// 段階ごとの学習の結果
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhaseReport {
+    pub phase: usize,
+    pub samples: usize,
+    /// 学習前の二乗平均誤差 (石数)
+    pub rmse_before: f64,
+    /// 学習後の二乗平均誤差 (石数)
+    pub rmse_after: f64,
+}
+
+/// `weights` の二乗平均誤差 (石数)
+fn rmse(weights: &Weights, samples: &[&Sample]) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let sum: f64 = samples
+        .iter()
+        .map(|s| (weights.score(&s.features) - s.target * SCALE) as f64)
+        .map(|e| e * e)
+        .sum();
+    (sum / samples.len() as f64).sqrt() / SCALE as f64
+}
+
+/// 1つの段階の重み `initial` を `samples` で学習した値を返します。
+fn train_phase(initial: &[i16], samples: &[&Sample], config: &TrainConfig) -> Vec<i16> {
+    let mobility = initial.len() - 1;
+    let prior: Vec<f64> = initial.iter().map(|&w| w as f64).collect();
+    let mut weights = prior.clone();
+    // 各重みの出現回数 (着手可能数は値の二乗和)
+    let mut counts = vec![0.0; initial.len()];
+    for sample in samples {
+        for &index in &sample.features.indexes {
+            counts[index] += 1.0;
+        }
+        counts[mobility] += (sample.features.mobility as f64).powi(2);
+    }
+    // 1局面の評価値に効く重みの数で割って、全ての重みを一度に動かしても行き過ぎないようにする
+    let step = 1.0 / (eval::features().len() + 1) as f64;
+    let limit = (64 * SCALE) as f64;
+    for _ in 0..config.epochs {
+        let mut gradient = vec![0.0; initial.len()];
+        for sample in samples {
+            let features = &sample.features;
+            let predicted: f64 = features
+                .indexes
+                .iter()
+                .map(|&index| weights[index])
+                .sum::<f64>()
+                + weights[mobility] * features.mobility as f64;
+            let error = (sample.target * SCALE) as f64 - predicted.clamp(-limit, limit);
+            for &index in &features.indexes {
+                gradient[index] += error;
+            }
+            gradient[mobility] += error * features.mobility as f64;
+        }
+        for (i, weight) in weights.iter_mut().enumerate() {
+            if counts[i] > 0.0 {
+                let regularization = config.lambda * (*weight - prior[i]);
+                *weight += step * (gradient[i] - regularization) / (counts[i] + config.lambda);
+            }
+        }
+    }
+    weights
+        .iter()
+        .map(|w| w.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16)
+        .collect()
+}
+
+/// `samples` で `weights` を段階ごとに学習し、段階ごとの結果を返します。
+///
+/// データのない段階の重みは変えません。
+pub fn train(weights: &mut Weights, samples: &[Sample], config: &TrainConfig) -> Vec<PhaseReport> {
+    let mut by_phase: Vec<Vec<&Sample>> = vec![vec![]; NUM_PHASES];
+    for sample in samples {
+        by_phase[sample.features.phase].push(sample);
+    }
+    let initial = weights.clone();
+    let trained: Vec<Vec<i16>> = by_phase
+        .par_iter()
+        .enumerate()
+        .map(|(phase, samples)| train_phase(initial.phase(phase), samples, config))
+        .collect();
+    for (phase, values) in trained.iter().enumerate() {
+        weights.phase_mut(phase).copy_from_slice(values);
+    }
+    by_phase
+        .iter()
+        .enumerate()
+        .filter(|(_, samples)| !samples.is_empty())
+        .map(|(phase, samples)| PhaseReport {
+            phase,
+            samples: samples.len(),
+            rmse_before: rmse(&initial, samples),
+            rmse_after: rmse(weights, samples),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    /// ランダムに終局まで打った対局
+    fn random_game() -> Game {
+        let mut rng = rand::thread_rng();
+        let mut game = Game::new();
+        while !game.is_over() {
+            if game.pass_if_needed() {
+                continue;
+            }
+            let moves = game.board().valid_moves(game.side_to_move());
+            game.play(Move::Mv(moves[rng.gen_range(0..moves.len())]))
+                .unwrap();
+        }
+        game
+    }
+
+    #[test]
+    fn test_read_records() {
+        let game = random_game();
+        let record = format_record(&game);
+        let samples = read_samples(record.as_bytes()).unwrap();
+        let moves = game.kihu().iter().filter(|m| **m != Move::Pass).count();
+        assert_eq!(samples.len(), moves);
+        // 最初の局面は黒番で、黒から見た最終石差が目標
+        let score = game.final_score().unwrap();
+        assert_eq!(samples[0].target, score);
+        assert_eq!(samples[1].target, -score);
+
+        // 石差を省略しても、終局していれば求められる
+        let (moves, _) = record.split_once(' ').unwrap();
+        assert_eq!(read_samples(moves.as_bytes()).unwrap(), samples);
+
+        let wrong = format!("{moves} {:+}", score + 2);
+        assert!(matches!(
+            read_samples(wrong.as_bytes()),
+            Err(ReadSamplesError::Parse { line: 1, .. })
+        ));
+        // 途中までの棋譜には石差が必要
+        assert!(read_samples("F5D6C3".as_bytes()).is_err());
+        assert_eq!(read_samples("F5D6C3 +10".as_bytes()).unwrap().len(), 3);
+        // ASCII 以外の文字があってもパニックしない
+        for record in ["F５D6 +10", "F5Ｄ +10", "é5D6 +10"] {
+            assert!(
+                matches!(
+                    read_samples(record.as_bytes()),
+                    Err(ReadSamplesError::Parse { line: 1, .. })
+                ),
+                "{record}"
+            );
+        }
+        assert!(matches!(
+            read_samples("# comment\nF5F5 +10".as_bytes()),
+            Err(ReadSamplesError::IllegalMove { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_read_positions() {
+        let position = Position {
+            board: Board::new(),
+            color: Color::White,
+        };
+        let samples = read_samples(format!("{position} -4\n\n").as_bytes()).unwrap();
+        assert_eq!(samples, vec![Sample::new(&Board::new(), Color::White, -4)]);
+        assert!(read_samples(format!("{position}").as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_train_reduces_error() {
+        let samples: Vec<Sample> = (0..40)
+            .flat_map(|_| {
+                let record = format_record(&random_game());
+                read_samples(record.as_bytes()).unwrap()
+            })
+            .collect();
+        let mut weights = Weights::zero();
+        let config = TrainConfig {
+            epochs: 20,
+            ..TrainConfig::default()
+        };
+        let reports = train(&mut weights, &samples, &config);
+        assert_eq!(
+            reports.iter().map(|r| r.samples).sum::<usize>(),
+            samples.len()
+        );
+        for report in &reports {
+            assert!(report.rmse_after < report.rmse_before, "{report:?}");
+        }
+        assert!(weights.phase(0).iter().any(|&w| w != 0));
+        // 終局まで打った対局なら、全ての段階にデータがある
+        assert_eq!(reports.len(), NUM_PHASES);
+    }
+}
@@ -0,0 +1,304 @@
+//! 読み切りと中盤探索の置換表です。
+//!
+//! 終盤の値は局面だけで決まるので、どの探索窓で求めた上界・下界でも共有できます。
+//! 中盤探索の値は読んだ深さで変わるので、中盤探索は別の表を使い、深さも一緒に保存します。
+//! 各エントリはキーとデータの2つの `AtomicU64` で、キーにはハッシュ値とデータの xor を
+//! 書き込みます (lockless hashing)。別スレッドの書き込みと混ざったエントリは
+//! キーが一致しなくなるので、ロックを取らずに読み書きできます。
+//!
+//! 2エントリで1バケットとし、1つ目は深く読んだ (読むのが重い) 局面を優先して残し、
+//! 2つ目は常に上書きします。
+
+use crate::square::Square;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::OnceLock;
+
+/// 共有の置換表のバケット数 (2の累乗、1バケット32バイト)
+const DEFAULT_BUCKETS: usize = 1 << 20;
+
+const NO_MOVE: u64 = 0xff;
+
+/// 値の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    /// 正確な値
+    Exact,
+    /// 本当の値はこれ以上
+    Lower,
+    /// 本当の値はこれ以下
+    Upper,
+}
+
+impl Bound {
+    /// fail-soft の探索結果 `value` を窓 `(alpha, beta)` で分類します。
+    pub fn classify(value: i32, alpha: i32, beta: i32) -> Self {
+        if value <= alpha {
+            Self::Upper
+        } else if value >= beta {
+            Self::Lower
+        } else {
+            Self::Exact
+        }
+    }
+}
+
+/// 置換表から読み出したエントリ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    /// 手番側から見た石差 (中盤探索では評価値)
+    pub score: i32,
+    pub bound: Bound,
+    pub best_move: Option<Square>,
+    /// 読んだ深さ (読み切りでは空きマス数)
+    pub depth: u8,
+}
+
+impl Entry {
+    /// 窓 `(alpha, beta)` の探索結果としてそのまま返せる値があれば返します。
+    pub fn cutoff(&self, alpha: i32, beta: i32) -> Option<i32> {
+        match self.bound {
+            Bound::Exact => Some(self.score),
+            Bound::Lower if self.score >= beta => Some(self.score),
+            Bound::Upper if self.score <= alpha => Some(self.score),
+            _ => None,
+        }
+    }
+
+    // score: 0..16, bound: 16..18, best_move: 24..32, depth: 32..40, generation: 40..48
+    fn pack(&self, generation: u8) -> u64 {
+        let bound = match self.bound {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2,
+        };
+        let best_move = self.best_move.map_or(NO_MOVE, |sq| sq.index() as u64);
+        (self.score as i16 as u16 as u64)
+            | bound << 16
+            | best_move << 24
+            | (self.depth as u64) << 32
+            | (generation as u64) << 40
+    }
+
+    fn unpack(data: u64) -> Self {
+        let bound = match (data >> 16) & 0x3 {
+            0 => Bound::Exact,
+            1 => Bound::Lower,
+            _ => Bound::Upper,
+        };
+        let best_move = (data >> 24) & 0xff;
+        Self {
+            score: data as u16 as i16 as i32,
+            bound,
+            best_move: (best_move != NO_MOVE)
+                .then(|| Square::from_index_unchecked(best_move as usize)),
+            depth: (data >> 32) as u8,
+        }
+    }
+}
+
+fn generation_of(data: u64) -> u8 {
+    (data >> 40) as u8
+}
+
+#[derive(Default)]
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+impl Slot {
+    fn load(&self, hash: u64) -> Option<u64> {
+        let key = self.key.load(Ordering::Relaxed);
+        let data = self.data.load(Ordering::Relaxed);
+        // data == 0 は一度も書かれていないスロット
+        (data != 0 && key ^ data == hash).then_some(data)
+    }
+
+    fn store(&self, hash: u64, data: u64) {
+        self.key.store(hash ^ data, Ordering::Relaxed);
+        self.data.store(data, Ordering::Relaxed);
+    }
+}
+
+/// 置換表のヒット率の統計
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TableStats {
+    pub probes: u64,
+    pub hits: u64,
+    pub stores: u64,
+}
+
+impl TableStats {
+    pub fn hit_rate(&self) -> f64 {
+        if self.probes == 0 {
+            0.0
+        } else {
+            self.hits as f64 / self.probes as f64
+        }
+    }
+}
+
+/// スレッド間で共有できる置換表
+///
+/// ```
+/// use fl_reversi_rs::transposition::{Bound, Entry, TranspositionTable};
+///
+/// let tt = TranspositionTable::new(1024);
+/// let entry = Entry { score: 4, bound: Bound::Lower, best_move: "C4".parse().ok(), depth: 10 };
+/// tt.store(0x1234, entry);
+/// assert_eq!(tt.probe(0x1234), Some(entry));
+/// assert_eq!(tt.probe(0x5678), None);
+/// ```
+pub struct TranspositionTable {
+    buckets: Box<[[Slot; 2]]>,
+    generation: AtomicU8,
+    probes: AtomicU64,
+    hits: AtomicU64,
+    stores: AtomicU64,
+}
+
+impl TranspositionTable {
+    /// `buckets` 個 (2の累乗に切り上げ) のバケットを持つ置換表を作ります。
+    pub fn new(buckets: usize) -> Self {
+        let buckets = buckets.max(1).next_power_of_two();
+        Self {
+            buckets: (0..buckets).map(|_| Default::default()).collect(),
+            generation: AtomicU8::new(1),
+            probes: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            stores: AtomicU64::new(0),
+        }
+    }
+
+    /// 読み切りで共有する置換表
+    pub fn shared() -> &'static Self {
+        static TABLE: OnceLock<TranspositionTable> = OnceLock::new();
+        TABLE.get_or_init(|| Self::new(DEFAULT_BUCKETS))
+    }
+
+    fn bucket(&self, hash: u64) -> &[Slot; 2] {
+        &self.buckets[hash as usize & (self.buckets.len() - 1)]
+    }
+
+    pub fn probe(&self, hash: u64) -> Option<Entry> {
+        self.bucket(hash)
+            .iter()
+            .find_map(|slot| slot.load(hash))
+            .map(Entry::unpack)
+    }
+
+    pub fn store(&self, hash: u64, entry: Entry) {
+        let generation = self.generation.load(Ordering::Relaxed);
+        let data = entry.pack(generation);
+        let [deep, recent] = self.bucket(hash);
+        let old = deep.data.load(Ordering::Relaxed);
+        let replace_deep = old == 0
+            || deep.load(hash).is_some()
+            || generation_of(old) != generation
+            || Entry::unpack(old).depth <= entry.depth;
+        if replace_deep {
+            deep.store(hash, data);
+        } else {
+            recent.store(hash, data);
+        }
+    }
+
+    /// 新しい探索を始めます。前の探索のエントリは優先せずに上書きされるようになります。
+    pub fn new_search(&self) {
+        let next = self
+            .generation
+            .load(Ordering::Relaxed)
+            .wrapping_add(1)
+            .max(1);
+        self.generation.store(next, Ordering::Relaxed);
+    }
+
+    /// 探索器ごとに数えた回数を足し込みます。
+    pub fn add_stats(&self, stats: TableStats) {
+        self.probes.fetch_add(stats.probes, Ordering::Relaxed);
+        self.hits.fetch_add(stats.hits, Ordering::Relaxed);
+        self.stores.fetch_add(stats.stores, Ordering::Relaxed);
+    }
+
+    /// これまでの統計
+    pub fn stats(&self) -> TableStats {
+        TableStats {
+            probes: self.probes.load(Ordering::Relaxed),
+            hits: self.hits.load(Ordering::Relaxed),
+            stores: self.stores.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn clear(&self) {
+        for slot in self.buckets.iter().flatten() {
+            slot.key.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
+        }
+        for counter in [&self.probes, &self.hits, &self.stores] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(score: i32, bound: Bound, depth: u8) -> Entry {
+        Entry {
+            score,
+            bound,
+            best_move: None,
+            depth,
+        }
+    }
+
+    #[test]
+    fn test_pack_round_trip() {
+        for score in [-8192, -64, -1, 0, 1, 64, 8192] {
+            for bound in [Bound::Exact, Bound::Lower, Bound::Upper] {
+                for best_move in [None, Square::from_index(0), Square::from_index(63)] {
+                    let e = Entry {
+                        score,
+                        bound,
+                        best_move,
+                        depth: 20,
+                    };
+                    assert_eq!(Entry::unpack(e.pack(7)), e);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_replacement() {
+        let tt = TranspositionTable::new(1);
+        tt.store(1, entry(10, Bound::Exact, 12));
+        // 浅いエントリは2つ目のスロットに入り、深いエントリを追い出さない
+        tt.store(2, entry(20, Bound::Exact, 5));
+        tt.store(3, entry(30, Bound::Exact, 6));
+        assert_eq!(tt.probe(1).map(|e| e.score), Some(10));
+        assert_eq!(tt.probe(2), None);
+        assert_eq!(tt.probe(3).map(|e| e.score), Some(30));
+
+        // 同じ局面は浅くても上書きする
+        tt.store(1, entry(-4, Bound::Upper, 3));
+        assert_eq!(tt.probe(1).map(|e| e.score), Some(-4));
+
+        // 前の探索のエントリは優先しない
+        tt.store(1, entry(10, Bound::Exact, 12));
+        tt.new_search();
+        tt.store(4, entry(40, Bound::Lower, 5));
+        assert_eq!(tt.probe(1), None);
+        assert_eq!(tt.probe(4).map(|e| e.score), Some(40));
+    }
+
+    #[test]
+    fn test_cutoff() {
+        assert_eq!(entry(3, Bound::Exact, 0).cutoff(-1, 1), Some(3));
+        assert_eq!(entry(3, Bound::Lower, 0).cutoff(-1, 1), Some(3));
+        assert_eq!(entry(3, Bound::Lower, 0).cutoff(-1, 5), None);
+        assert_eq!(entry(-3, Bound::Upper, 0).cutoff(-1, 1), Some(-3));
+        assert_eq!(entry(-3, Bound::Upper, 0).cutoff(-5, 1), None);
+    }
+}
@@ -3,35 +3,25 @@ use super::book::DATA;
 use super::proto::Move;
 use std::collections::HashMap;
 
-use crate::bit_othello::Pos;
+use crate::square::Square;
+use crate::symmetry::Symmetry;
 
-fn str2pos(s: &str) -> Pos {
-    let x = s.chars().nth(0).unwrap() as u32 - 'A' as u32 + 1;
-    let y = s.chars().nth(1).unwrap() as u32 - '1' as u32 + 1;
-    (x as usize, y as usize)
-}
+/// 初期配置を変えない4つの対称変換
+const BOOK_SYMMETRIES: [Symmetry; 4] = [
+    Symmetry::Identity,
+    Symmetry::AntiTranspose,
+    Symmetry::Rotate180,
+    Symmetry::Transpose,
+];
 
-fn move2str(m: Move) -> String {
-    if let Move::Mv { x_ah, y_18 } = m {
-        format!(
-            "{}{}",
-            ('A' as u8 + x_ah as u8 - 1) as char,
-            ('1' as u8 + y_18 as u8 - 1) as char
-        )
-    } else {
-        "".to_string()
-    }
-}
-
-fn rotate_pos(pos: Pos, rot: u32) -> Pos {
-    let (x, y) = pos;
-    match rot {
-        0 => (x, y),
-        1 => (9 - y, 9 - x),
-        2 => (9 - x, 9 - y),
-        3 => (y, x),
-        _ => (x, y),
-    }
+/// 棋譜を `sym` で変換して "C4C3..." の形式にします。パスは書きません。
+fn kihu_to_string(kihu: &[Move], sym: Symmetry) -> String {
+    kihu.iter()
+        .filter_map(|m| match m {
+            Move::Mv(square) => Some(square.transform(sym).to_string()),
+            _ => None,
+        })
+        .collect()
 }
 
 pub fn initialize_book_dict() -> HashMap<String, String> {
@@ -48,28 +38,16 @@ pub fn initialize_book_dict() -> HashMap<String, String> {
     book_dict
 }
 
-pub fn decide(kihu: &Vec<Move>, book_dict: &HashMap<String, String>) -> Option<Move> {
-    let kihu_str: String = kihu.iter().map(|m| move2str(*m)).collect();
+pub fn decide(kihu: &[Move], book_dict: &HashMap<String, String>) -> Option<Move> {
     let mut pos: Option<Move> = None;
-    for rot in 0..4 {
-        let kihu_rot = kihu_str.chars().collect::<Vec<char>>();
-        let mut kihu_rot_str = String::new();
-        for i in 0..kihu_rot.len() / 2 {
-            let pos = str2pos(&kihu_str[i * 2..i * 2 + 2]);
-            let pos_rot = rotate_pos(pos, rot);
-            kihu_rot_str.push(('A' as u8 + pos_rot.0 as u8 - 1) as char);
-            kihu_rot_str.push(('1' as u8 + pos_rot.1 as u8 - 1) as char);
-        }
-        if let Some(value) = book_dict.get(&kihu_rot_str) {
-            let next_move = str2pos(value);
-            let rotated_next_move = rotate_pos(next_move, rot);
-            pos = Some(Move::Mv {
-                x_ah: rotated_next_move.0 as u32,
-                y_18: rotated_next_move.1 as u32,
-            });
+    for sym in BOOK_SYMMETRIES {
+        if let Some(value) = book_dict.get(&kihu_to_string(kihu, sym)) {
+            if let Ok(next_move) = value.parse::<Square>() {
+                pos = Some(Move::Mv(next_move.transform(sym.inverse())));
+            }
         }
     }
-    return pos;
+    pos
 }
 
 #[cfg(test)]
@@ -93,7 +71,7 @@ mod tests {
         println!("count: {}", count);
         println!("len: {}", len_dict);
         println!("elapsed: {:?}", elapsed);
-        let move_list = vec![Move::Mv { x_ah: 4, y_18: 3 }];
+        let move_list = vec![Move::Mv("D3".parse().unwrap())];
         let next_move = decide(&move_list, &book_dict);
         println!("{:?}", next_move);
     }
@@ -0,0 +1,162 @@
+//! 盤面のZobristハッシュです。
+//!
+//! 各マスの黒石・白石に乱数を割り当て、置かれている石の乱数の xor をハッシュ値とします。
+//! 1バイト (1列) ごとに表を引くので、裏返った石のマスクからまとめて差分を計算できます。
+
+use crate::bit_othello::Board;
+use crate::proto::Color;
+use crate::square::Square;
+
+const fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    (state, z ^ (z >> 31))
+}
+
+/// `KEYS[0]` が黒、`KEYS[1]` が白の各マスの乱数
+const KEYS: [[u64; 64]; 2] = {
+    let mut keys = [[0; 64]; 2];
+    let mut state = 0x0123456789abcdef;
+    let mut c = 0;
+    while c < 2 {
+        let mut square = 0;
+        while square < 64 {
+            let (next, key) = splitmix64(state);
+            state = next;
+            keys[c][square] = key;
+            square += 1;
+        }
+        c += 1;
+    }
+    keys
+};
+
+/// `BYTE_KEYS[c][i][b]` は列 `i` にバイト `b` の形で石があるときの乱数の xor
+static BYTE_KEYS: [[[u64; 256]; 8]; 2] = {
+    let mut table = [[[0; 256]; 8]; 2];
+    let mut c = 0;
+    while c < 2 {
+        let mut i = 0;
+        while i < 8 {
+            let mut b = 0;
+            while b < 256 {
+                let mut key = 0;
+                let mut bit = 0;
+                while bit < 8 {
+                    if b & (1 << bit) != 0 {
+                        key ^= KEYS[c][i * 8 + bit];
+                    }
+                    bit += 1;
+                }
+                table[c][i][b] = key;
+                b += 1;
+            }
+            i += 1;
+        }
+        c += 1;
+    }
+    table
+};
+
+/// 白番のときに xor する乱数
+pub const WHITE_TO_MOVE: u64 = 0x5f1c3a7e9d2b4c68;
+
+/// 黒なら 0、白なら 1
+pub(crate) fn color_index(color: Color) -> usize {
+    if color == Color::Black {
+        0
+    } else {
+        1
+    }
+}
+
+fn bits_key(bits: u64, c: usize) -> u64 {
+    let mut key = 0;
+    for i in 0..8 {
+        key ^= BYTE_KEYS[c][i][((bits >> (i * 8)) & 0xff) as usize];
+    }
+    key
+}
+
+/// 盤面全体からハッシュ値を計算します。
+pub fn hash(board: &Board) -> u64 {
+    bits_key(board.black, 0) ^ bits_key(board.white, 1)
+}
+
+/// 盤面と手番を合わせたハッシュ値を計算します。
+pub fn hash_with_color(board: &Board, color: Color) -> u64 {
+    if color == Color::Black {
+        hash(board)
+    } else {
+        hash(board) ^ WHITE_TO_MOVE
+    }
+}
+
+/// `color` が `square` に打って `flips` を裏返したときのハッシュ値の差分
+pub fn move_key(square: Square, flips: u64, color: Color) -> u64 {
+    KEYS[color_index(color)][square.index()] ^ bits_key(flips, 0) ^ bits_key(flips, 1)
+}
+
+/// `make`/`unmake` に合わせてハッシュ値を更新します。xor なので打つときも戻すときも同じです。
+pub fn update(hash: u64, square: Square, flips: u64, color: Color) -> u64 {
+    hash ^ move_key(square, flips, color)
+}
+
+impl Board {
+    pub fn zobrist(&self) -> u64 {
+        hash(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_incremental_update() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            let mut board = Board::new();
+            let mut color = Color::Black;
+            let mut h = hash(&board);
+            loop {
+                let moves = board.valid_moves(color);
+                if moves.is_empty() {
+                    if board.valid_moves(color.opposite()).is_empty() {
+                        break;
+                    }
+                    color = color.opposite();
+                    continue;
+                }
+                let square = moves[rng.gen_range(0..moves.len())];
+                let flips = board.flips(square, color);
+                board.make(square, flips, color);
+                h = update(h, square, flips, color);
+                assert_eq!(h, hash(&board));
+                color = color.opposite();
+            }
+        }
+    }
+
+    #[test]
+    fn test_board_as_key() {
+        let board = Board::new();
+        let mut set = HashSet::new();
+        for square in board.moves(Color::Black) {
+            let mut board = board.clone();
+            let flips = board.flips(square, Color::Black);
+            board.make(square, flips, Color::Black);
+            assert!(set.insert(board.zobrist()));
+        }
+        assert_eq!(set.len(), 4);
+        assert_ne!(
+            hash_with_color(&board, Color::Black),
+            hash_with_color(&board, Color::White)
+        );
+        assert_eq!(board, Board::new());
+    }
+}
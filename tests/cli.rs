@@ -0,0 +1,122 @@
+use std::process::Command;
+
+const POSITION: &str = "--XXXXX--OOOXX-O-OOOXXOX-OXOXOXXOXXXOXXX--XOXOXX-XXXOOO--OOOOO-- X";
+
+/// `solve` を一時ファイルの内容で実行し、終了コードと標準出力を返す
+fn run_solve(name: &str, content: &str) -> (bool, String) {
+    let path = std::env::temp_dir().join(format!("fl-reversi-{}-{name}.txt", std::process::id()));
+    std::fs::write(&path, content).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_fl-reversi-rs"))
+        .arg("solve")
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    (
+        output.status.success(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
+
+#[test]
+fn test_solve_checks_expected_scores() {
+    let (ok, stdout) = run_solve("ok", &format!("# comment\n\n{POSITION} +18\n{POSITION}\n"));
+    assert!(ok, "{stdout}");
+    assert!(stdout.contains("line 3: G8 +18 ok"), "{stdout}");
+    assert!(stdout.contains("total: 2 positions"), "{stdout}");
+
+    let (ok, stdout) = run_solve("ng", &format!("{POSITION} +20 # wrong\n"));
+    assert!(!ok);
+    assert!(stdout.contains("(expected +20) FAILED"), "{stdout}");
+}
+
+#[test]
+fn test_probcut_writes_parameters() {
+    let dir = std::env::temp_dir();
+    let positions = dir.join(format!("fl-reversi-{}-probcut-in.txt", std::process::id()));
+    let output = dir.join(format!("fl-reversi-{}-probcut-out.txt", std::process::id()));
+    std::fs::write(&positions, format!("{POSITION}\n")).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_fl-reversi-rs"))
+        .arg("probcut")
+        .arg(&output)
+        .arg(&positions)
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success());
+    let text = std::fs::read_to_string(&output).unwrap();
+    assert!(text.starts_with("probcut 1\nthreshold "), "{text}");
+
+    // 書き出したファイルは `-m` で読める。壊れたファイルなら起動しない
+    let run_with = |path: &std::path::Path| {
+        Command::new(env!("CARGO_BIN_EXE_fl-reversi-rs"))
+            .arg("-m")
+            .arg(path)
+            .arg("solve")
+            .arg(&positions)
+            .output()
+            .unwrap()
+            .status
+    };
+    assert!(run_with(&output).success());
+    std::fs::write(&output, "not a probcut file\n").unwrap();
+    assert!(!run_with(&output).success());
+
+    std::fs::remove_file(&positions).unwrap();
+    std::fs::remove_file(&output).unwrap();
+}
+
+#[test]
+fn test_train_from_self_play_records() {
+    let dir = std::env::temp_dir();
+    let records = dir.join(format!("fl-reversi-{}-records.txt", std::process::id()));
+    let weights = dir.join(format!("fl-reversi-{}-eval.bin", std::process::id()));
+    let status = Command::new(env!("CARGO_BIN_EXE_fl-reversi-rs"))
+        .args(["-t", "5", "selfplay", "1", "monte", "pvs"])
+        .arg(&records)
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success());
+    let text = std::fs::read_to_string(&records).unwrap();
+    assert_eq!(text.lines().count(), 2, "{text}");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fl-reversi-rs"))
+        .arg("train")
+        .arg(&weights)
+        .arg(&records)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("rmse"), "{stdout}");
+
+    // 書き出した重みは `-w` で読める
+    let mut positions = records.clone();
+    positions.set_extension("positions");
+    std::fs::write(&positions, format!("{POSITION}\n")).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_fl-reversi-rs"))
+        .arg("-w")
+        .arg(&weights)
+        .arg("solve")
+        .arg(&positions)
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success());
+
+    // 壊れた学習データでは重みを書き出さない
+    std::fs::remove_file(&weights).unwrap();
+    std::fs::write(&records, "F5F5 +10\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_fl-reversi-rs"))
+        .arg("train")
+        .arg(&weights)
+        .arg(&records)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(!weights.exists());
+
+    std::fs::remove_file(&records).unwrap();
+    std::fs::remove_file(&positions).unwrap();
+}
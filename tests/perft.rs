@@ -0,0 +1,79 @@
+use fl_reversi_rs::{Board, Color};
+
+/// 初期局面からの perft の既知の値 (パスも1手として数える)
+const PERFT: [u64; 14] = [
+    1,
+    4,
+    12,
+    56,
+    244,
+    1396,
+    8200,
+    55092,
+    390216,
+    3005288,
+    24571284,
+    212258800,
+    1939886636,
+    18429641748,
+];
+
+fn check(depth: u32) {
+    let board = Board::new();
+    assert_eq!(
+        board.perft(Color::Black, depth),
+        PERFT[depth as usize],
+        "perft({depth})"
+    );
+}
+
+#[test]
+fn test_perft_shallow() {
+    for depth in 0..=8 {
+        check(depth);
+    }
+}
+
+#[test]
+fn test_perft_9() {
+    check(9);
+}
+
+#[test]
+fn test_perft_10() {
+    check(10);
+}
+
+#[test]
+fn test_perft_11() {
+    check(11);
+}
+
+#[test]
+#[ignore = "slow; run with --ignored"]
+fn test_perft_12() {
+    check(12);
+}
+
+#[test]
+#[ignore = "slow; run with --ignored"]
+fn test_perft_13() {
+    check(13);
+}
+
+#[test]
+fn test_perft_symmetric_openings() {
+    // 初手4つは対称なので、どれを打っても同じ数になる
+    let board = Board::new();
+    let counts: Vec<u64> = board
+        .moves(Color::Black)
+        .map(|square| {
+            let mut board = board.clone();
+            board.do_move(fl_reversi_rs::Move::Mv(square), Color::Black);
+            board.perft(Color::White, 7)
+        })
+        .collect();
+    assert_eq!(counts.len(), 4);
+    assert!(counts.iter().all(|&c| c == counts[0]));
+    assert_eq!(counts.iter().sum::<u64>(), PERFT[8]);
+}
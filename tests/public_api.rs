@@ -0,0 +1,134 @@
+use fl_reversi_rs::ai_decide::{Config, Engine};
+use fl_reversi_rs::depth_first_search::Proof;
+use fl_reversi_rs::eval;
+use fl_reversi_rs::game::Game;
+use fl_reversi_rs::{ai_decide, depth_first_search, midgame, monte, self_play, use_book};
+use fl_reversi_rs::{Board, Color, Move, Position, Square};
+
+fn sq(s: &str) -> Square {
+    s.parse().unwrap()
+}
+
+/// 常に最初の合法手を打ち続けて `stones` 個まで進めた局面を返す
+fn play_first_moves(stones: u64) -> (Board, Color) {
+    let mut game = Game::new();
+    while game.board().sum_stones() < stones && !game.is_over() {
+        if game.pass_if_needed() {
+            continue;
+        }
+        let square = game.board().valid_moves(game.side_to_move())[0];
+        game.play(Move::Mv(square)).unwrap();
+    }
+    (game.board().clone(), game.side_to_move())
+}
+
+#[test]
+fn test_opening_moves() {
+    let mut board = Board::new();
+    let moves: Vec<String> = board
+        .valid_moves(Color::Black)
+        .iter()
+        .map(Square::to_string)
+        .collect();
+    assert_eq!(moves, vec!["C4", "D3", "E6", "F5"]);
+
+    let c4 = Move::Mv(sq("C4"));
+    assert!(board.check_valid_move(c4, Color::Black));
+    board.do_move(c4, Color::Black);
+    assert_eq!(board.count_stones(), (4, 1));
+    assert_eq!(board.diff_stones(Color::White), -3);
+    assert_eq!(board.valid_moves(Color::White).len(), 3);
+}
+
+#[test]
+fn test_book_lookup() {
+    let book_dict = use_book::initialize_book_dict();
+    let kihu = vec![Move::Mv(sq("C4"))];
+    assert_eq!(
+        use_book::decide(&kihu, &book_dict),
+        Some(Move::Mv(sq("C3")))
+    );
+    // 対称な局面 (F5) でも定石が使える
+    let kihu = vec![Move::Mv(sq("F5"))];
+    assert_eq!(
+        use_book::decide(&kihu, &book_dict),
+        Some(Move::Mv(sq("F6")))
+    );
+}
+
+#[test]
+fn test_perfect_read_near_end() {
+    let (board, color) = play_first_moves(56);
+    let exact = depth_first_search::perfect_read(&board, color, 10000);
+    assert!(board.check_valid_move(exact.best_move, color));
+    assert_eq!(exact.proof, Proof::Exact);
+    assert!(exact.stats.nodes > 0 && !exact.stats.timed_out);
+    assert_eq!(exact.stats.depth, 8);
+    // 最善手順を打つと読み切った石差で終局する
+    let mut replay = Game::from_position(Position {
+        board: board.clone(),
+        color,
+    });
+    for &m in &exact.pv {
+        replay.play(m).unwrap();
+    }
+    assert!(replay.is_over());
+    assert_eq!(replay.board().diff_stones(color), exact.value);
+    let wld = depth_first_search::decide(&board, color, 10000);
+    assert!(board.check_valid_move(wld.best_move, color));
+    assert_eq!(wld.proof, Proof::Wld);
+    assert_eq!(wld.value, exact.value.signum());
+}
+
+#[test]
+fn test_timeout_keeps_best_known_move() {
+    // 時間がなくても投了せず、合法手を返す
+    let board = Board::new();
+    let color = Color::Black;
+    let solution = depth_first_search::perfect_read(&board, color, 0);
+    assert_eq!(solution.proof, Proof::Unproven);
+    assert!(solution.stats.timed_out);
+    assert!(board.check_valid_move(solution.best_move, color));
+}
+
+#[test]
+fn test_engines_return_legal_moves() {
+    let board = Board::new();
+    let (mv, stats) = monte::decide(&board, Color::Black, 50);
+    assert!(board.check_valid_move(mv, Color::Black));
+    assert!(stats.nodes > 0);
+    let (mv, _) = monte::decide_with(&board, Color::Black, 50, eval::weights());
+    assert!(board.check_valid_move(mv, Color::Black));
+
+    let result = midgame::decide(&board, Color::Black, 50, eval::weights());
+    assert!(board.check_valid_move(result.best_move, Color::Black));
+
+    let book_dict = use_book::initialize_book_dict();
+    let (mv, _) = ai_decide::decide(&Game::new(), &book_dict);
+    assert!(board.check_valid_move(mv, Color::Black));
+}
+
+#[test]
+fn test_self_play_game() {
+    let config: Config = "monte,pvs".parse().unwrap();
+    assert_eq!(
+        (config.opening, config.midgame),
+        (Engine::Monte, Engine::Pvs)
+    );
+    assert_eq!(config.to_string(), "monte,pvs");
+    assert!("alphabeta".parse::<Config>().is_err());
+
+    // 時間を短くして1局打ち切れることを確かめる
+    let first = Config {
+        time_ms: 5,
+        ..config
+    };
+    let second = Config {
+        time_ms: 5,
+        ..Config::default()
+    };
+    let book_dict = use_book::initialize_book_dict();
+    let game = self_play::play_game(&[], &first, &second, &book_dict);
+    assert!(game.is_over());
+    assert!(game.final_score().unwrap().abs() <= 64);
+}